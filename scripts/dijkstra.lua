@@ -0,0 +1,36 @@
+
+                local dist = {}
+                local done = {}
+
+                -- odległości od pierwszego węzła, liczone po wagach krawędzi
+                start_node = graph:get_nodes()[1]
+                dist[start_node] = 0
+                set_color(start_node, [[#0f0]])  -- startowy węzeł na zielono
+                coroutine.yield()
+
+                while true do
+                    -- wybieramy najbliższy jeszcze nieodwiedzony węzeł
+                    local node = nil
+                    for n, d in pairs(dist) do
+                        if not done[n] and (node == nil or d < dist[node]) then
+                            node = n
+                        end
+                    end
+                    if node == nil then break end
+                    done[node] = true
+
+                    local neighbours = graph:get_neighbours(node)
+                    for _, n in ipairs(neighbours) do
+                        local d = dist[node] + graph:get_weight(node, n)
+                        if not done[n] and (dist[n] == nil or d < dist[n]) then
+                            dist[n] = d
+                            set_color(n, [[#f00]])  -- poprawiona odległość na czerwono
+                            coroutine.yield()
+                        end
+                    end
+
+                    -- węzeł ma już ostateczną odległość
+                    print(graph:node(node).label .. ": " .. dist[node])
+                    set_color(node, [[#00f]])
+                    coroutine.yield()
+                end
@@ -0,0 +1,24 @@
+                local size = 5
+                local grid = {}
+
+                -- dokładamy siatkę size x size obok istniejącego grafu
+                for row = 1, size do
+                    grid[row] = {}
+                    for col = 1, size do
+                        local node = add_node(row .. "," .. col, col * 150, -row * 150)
+                        grid[row][col] = node
+                        if col > 1 then add_edge(grid[row][col - 1], node) end
+                        if row > 1 then add_edge(grid[row - 1][col], node) end
+                        coroutine.yield()
+                    end
+                end
+
+                -- przekątna na czerwono, potem ją usuwamy
+                for i = 1, size do
+                    set_color(grid[i][i], [[#f00]])
+                    coroutine.yield()
+                end
+                for i = 1, size do
+                    remove_node(grid[i][i])
+                    coroutine.yield()
+                end
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use crate::components::*;
+
+pub const USAGE: &str = "\
+usage: grephite [OPTIONS] [FILE]
+
+Opens FILE in the graph viewer. Files ending in .graphml are read as GraphML,
+.dot and .gv as Graphviz DOT, .json as node-link JSON, anything else as an
+edge list.
+
+options:
+  --scripts-dir <DIR>   directory listed in the script window (default: scripts)
+  --repulsion <F>       repulsion force constant (default: 5000)
+  --gravity <F>         gravity force constant (default: 0.2)
+  --theta <F>           Barnes-Hut approximation, 0 for exact (default: 1.2)
+  --edge-influence <F>  power edge weights are raised to, 0 to ignore them (default: 1)
+  --no-physics          start with the physics simulation disabled
+  --fixed-timestep      step the simulation at a fixed rate, not once per frame
+  --prevent-overlap     push apart nodes that overlap
+  --spacing <F>         gap kept between nodes with --prevent-overlap (default: 10)
+  --seed <N>            seed for random placement (default: 0)
+  --mode <MODE>         initial mode: view, edit or script (default: view)
+  -h, --help            print this message";
+
+pub struct Cli {
+    pub path: Option<PathBuf>,
+    pub config: Config,
+    pub mode: AppMode,
+}
+
+pub enum CliError {
+    Help,
+    Invalid(String),
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Cli, CliError> {
+    let mut cli = Cli {
+        path: None,
+        config: Config::default(),
+        mode: AppMode::View,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--scripts-dir" => cli.config.scripts_dir = value(&arg, &mut args)?,
+            "--repulsion" => cli.config.k_r = number(&arg, &mut args)?,
+            "--gravity" => cli.config.k_g = number(&arg, &mut args)?,
+            "--theta" => cli.config.theta = number(&arg, &mut args)?,
+            "--edge-influence" => cli.config.edge_influence = number(&arg, &mut args)?,
+            "--no-physics" => cli.config.enabled = false,
+            "--fixed-timestep" => cli.config.fixed_timestep = true,
+            "--prevent-overlap" => cli.config.prevent_overlap = true,
+            "--spacing" => cli.config.min_spacing = number(&arg, &mut args)?,
+            "--seed" => {
+                let v = value(&arg, &mut args)?;
+                cli.config.seed = v.parse().map_err(|_| {
+                    CliError::Invalid(format!("`{arg}` expects a whole number, got `{v}`"))
+                })?;
+            }
+            "--mode" => {
+                cli.mode = match value(&arg, &mut args)?.as_str() {
+                    "view" => AppMode::View,
+                    "edit" => AppMode::Edit,
+                    "script" => AppMode::Script,
+                    other => return Err(CliError::Invalid(format!("unknown mode `{other}`"))),
+                }
+            }
+            _ if arg.starts_with('-') => {
+                return Err(CliError::Invalid(format!("unknown option `{arg}`")));
+            }
+            _ if cli.path.is_some() => {
+                return Err(CliError::Invalid(format!("unexpected argument `{arg}`")));
+            }
+            _ => cli.path = Some(PathBuf::from(arg)),
+        }
+    }
+    Ok(cli)
+}
+
+fn value(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<String, CliError> {
+    args.next()
+        .ok_or_else(|| CliError::Invalid(format!("`{flag}` expects a value")))
+}
+
+fn number(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<f32, CliError> {
+    let v = value(flag, args)?;
+    v.parse()
+        .map_err(|_| CliError::Invalid(format!("`{flag}` expects a number, got `{v}`")))
+}
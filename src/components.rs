@@ -1,29 +1,304 @@
 use bevy::prelude::*;
-use mlua::{Lua, Thread, ThreadStatus, UserData, UserDataMethods};
-use std::collections::HashMap;
+use mlua::{
+    AnyUserData, FromLua, Lua, MetaMethod, Table, Thread, UserData, UserDataFields,
+    UserDataMethods, Value,
+};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
+use bevy::tasks::Task;
+
+use crate::formats::{EdgeData, NodeData};
+use crate::layout::{LayoutKind, LayoutResult};
+use crate::physics::ForceState;
+
 #[derive(Component)]
 pub struct GNode {
     pub id: usize,
+    pub label: String,
 }
 
 #[derive(Component)]
 pub struct GEdge {
     pub from: Entity,
     pub to: Entity,
+    pub weight: f32,
+}
+
+/// Marks a node that the physics simulation leaves where it is; it still
+/// pushes and pulls the nodes around it.
+#[derive(Component)]
+pub struct Pinned;
+
+/// Text child of a `GEdge` showing its weight at the edge midpoint.
+#[derive(Component)]
+pub struct EdgeLabel;
+
+/// Arrowhead child of a `GEdge`, shown at the target end in directed graphs.
+#[derive(Component)]
+pub struct EdgeArrow;
+
+/// Ring child of a pinned `GNode`, so pins can be told apart at a glance.
+#[derive(Component)]
+pub struct PinMarker;
+
+/// Child of a `GEdge` drawing one straight segment of the edge: the shared
+/// unit square, stretched to the segment's length through its `Transform`.
+/// Edges without bends have a single segment, number 0.
+#[derive(Component)]
+pub struct EdgeLine(pub usize);
+
+/// Bend points of a `GEdge`, set by layouts that route edges around nodes.
+/// They only apply while both endpoints are still where the layout put
+/// them; once either node moves the edge is drawn straight again.
+#[derive(Component)]
+pub struct EdgeRoute {
+    pub from: Vec2,
+    pub to: Vec2,
+    pub bends: Vec<Vec2>,
+}
+
+impl EdgeRoute {
+    /// The points the edge passes through, from `start` to `end`.
+    pub fn path(route: Option<&EdgeRoute>, start: Vec2, end: Vec2) -> Vec<Vec2> {
+        let mut path = vec![start];
+        if let Some(route) = route
+            && route.from.distance(start) < 1.
+            && route.to.distance(end) < 1.
+        {
+            path.extend_from_slice(&route.bends);
+        }
+        path.push(end);
+        path
+    }
+}
+
+/// Marks a `GEdge` drawn faintly, such as the edges left out of the
+/// spanning tree by a tree layout.
+#[derive(Component)]
+pub struct Dimmed;
+
+/// Radius of the circle drawn for each node.
+pub const NODE_RADIUS: f32 = 50.;
+
+/// Meshes and materials shared by all nodes and edges, so drawing the graph
+/// does not add new assets every frame.
+#[derive(Resource)]
+pub struct GraphAssets {
+    pub node_mesh: Handle<Mesh>,
+    pub edge_mesh: Handle<Mesh>, // 1x1 square centred on the origin
+    pub arrow_mesh: Handle<Mesh>,
+    pub pin_mesh: Handle<Mesh>, // ring just outside the node circle
+    pub node_material: Handle<ColorMaterial>, // black, what new nodes start with
+    pub edge_material: Handle<ColorMaterial>,
+    pub dimmed_material: Handle<ColorMaterial>, // edges marked `Dimmed`
+    pub preview_material: Handle<ColorMaterial>, // edge being drawn in Edit mode
+    pub pin_material: Handle<ColorMaterial>,
+    node_materials: HashMap<[u8; 4], Handle<ColorMaterial>>,
 }
+
+impl FromWorld for GraphAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let node_mesh = meshes.add(Circle::new(NODE_RADIUS));
+        let edge_mesh = meshes.add(Rectangle::new(1., 1.));
+        // tip at the origin, pointing along +x
+        let arrow_mesh = meshes.add(Triangle2d::new(
+            Vec2::ZERO,
+            Vec2::new(-24., 10.),
+            Vec2::new(-24., -10.),
+        ));
+        let pin_mesh = meshes.add(Annulus::new(NODE_RADIUS + 4., NODE_RADIUS + 12.));
+
+        let mut materials = world.resource_mut::<Assets<ColorMaterial>>();
+        let edge_material = materials.add(Color::srgb(1., 0., 0.));
+        let dimmed_material = materials.add(Color::srgba(1., 0., 0., 0.2));
+        let preview_material = materials.add(Color::srgb(0.5, 0.5, 0.5));
+        let pin_material = materials.add(Color::srgb(0.1, 0.4, 1.));
+        let mut assets = GraphAssets {
+            node_mesh,
+            edge_mesh,
+            arrow_mesh,
+            pin_mesh,
+            node_material: Handle::default(),
+            edge_material,
+            dimmed_material,
+            preview_material,
+            pin_material,
+            node_materials: HashMap::new(),
+        };
+        assets.node_material = assets.material(&mut materials, Color::BLACK);
+        assets
+    }
+}
+
+impl GraphAssets {
+    /// The node material for `color`, created the first time it is asked for.
+    pub fn material(
+        &mut self,
+        materials: &mut Assets<ColorMaterial>,
+        color: Color,
+    ) -> Handle<ColorMaterial> {
+        self.node_materials
+            .entry(color.to_srgba().to_u8_array())
+            .or_insert_with(|| materials.add(color))
+            .clone()
+    }
+}
+
+/// Attributes read from a graph file that grephite has no use for itself,
+/// kept so that they are written back out on save.
+#[derive(Component, Default, Clone, Debug)]
+pub struct Attributes(pub BTreeMap<String, AttrValue>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttrValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<AttrValue>),
+    Map(BTreeMap<String, AttrValue>),
+}
+
+impl AttrValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            AttrValue::Int(i) => Some(*i as f64),
+            AttrValue::Float(f) => Some(*f),
+            AttrValue::Str(s) => s.parse().ok(),
+            AttrValue::Bool(_) | AttrValue::List(_) | AttrValue::Map(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttrValue::Bool(b) => write!(f, "{b}"),
+            AttrValue::Int(i) => write!(f, "{i}"),
+            AttrValue::Float(x) => write!(f, "{x}"),
+            AttrValue::Str(s) => write!(f, "{s}"),
+            // nested values are written as JSON
+            AttrValue::List(_) | AttrValue::Map(_) => {
+                write!(f, "{}", serde_json::Value::from(self))
+            }
+        }
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct DragState {
     pub dragging: Option<Entity>,
     pub offset: Vec2, // offset from mouse to node center
+    pub start: Vec2,  // where the dragged node was picked up
 }
-#[derive(Resource)]
+/// What an edge's weight changes in the force simulation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum EdgeForce {
+    #[default]
+    Attraction, // heavier edges pull harder
+    Length, // heavier edges are longer, like distances
+}
+
+#[derive(Resource, Clone)]
 pub struct Config {
     pub k_r: f32,
     pub k_g: f32,
+    pub theta: f32, // Barnes–Hut accuracy: 0 is exact, larger is faster and rougher
+    pub tolerance: f32, // converged once nodes move less than this per step (RMS)
+    pub fixed_timestep: bool, // step at a fixed rate instead of once per frame
+    pub scaling_ratio: f32, // multiplies repulsion: larger spreads the graph out
+    pub strong_gravity: bool, // gravity grows with distance instead of being constant
+    pub lin_log: bool, // logarithmic attraction, for tighter clusters
+    pub dissuade_hubs: bool, // divide attraction by the degree of the edge's source
+    pub edge_influence: f32, // edge weights count raised to this power: 0 ignores them
+    pub edge_force: EdgeForce,
+    pub edge_attribute: String, // numeric edge attribute used as the weight, if set
+    pub prevent_overlap: bool,  // push apart nodes closer than `min_spacing`
+    pub min_spacing: f32,       // gap kept between node circles
+    pub seed: u64,              // for random placement, so layouts can be reproduced
     pub enabled: bool,
     pub scripts_dir: String,
+    pub show_weights: bool,
+    pub layout: LayoutKind, // chosen in the physics settings window
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            k_r: 5000.,
+            k_g: 0.2,
+            theta: 1.2,
+            tolerance: 0.2,
+            fixed_timestep: false,
+            scaling_ratio: 1.,
+            strong_gravity: true,
+            lin_log: false,
+            dissuade_hubs: false,
+            edge_influence: 1.,
+            edge_force: EdgeForce::default(),
+            edge_attribute: String::new(),
+            prevent_overlap: false,
+            min_spacing: 10.,
+            seed: 0,
+            enabled: true,
+            scripts_dir: "scripts".to_string(),
+            show_weights: false,
+            layout: LayoutKind::default(),
+        }
+    }
+}
+
+/// Step size the simulation starts with; it shrinks every step down to 1.
+const START_TIMESTEP: f32 = 20.;
+/// Step size an edit or drag brings the simulation back up to.
+const REHEAT_TIMESTEP: f32 = 4.;
+
+/// Progress of the live force simulation. Replaced when a graph is opened,
+/// so that a file always starts out the same way.
+#[derive(Resource)]
+pub struct Simulation {
+    pub timestep: f32,
+    pub energy: f32, // sum of the squared distances nodes moved in the last step
+    pub converged: bool,
+    pub iterations: usize, // how many steps "Run" asks for
+    pub pending: usize,    // "Run" steps still to take, even with physics off
+    pub lag: f32,          // seconds not yet simulated in fixed timestep mode
+    pub forces: ForceState,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Simulation {
+            timestep: START_TIMESTEP,
+            energy: 0.,
+            converged: false,
+            iterations: 100,
+            pending: 0,
+            lag: 0.,
+            forces: ForceState::default(),
+        }
+    }
+}
+
+impl Simulation {
+    /// Starts the simulation again after it converged.
+    pub fn reheat(&mut self) {
+        self.converged = false;
+        self.timestep = self.timestep.max(REHEAT_TIMESTEP);
+    }
+}
+
+/// The graph file currently open, and the error from the last attempt to
+/// read or write it, if any.
+#[derive(Resource, Default)]
+pub struct FileState {
+    pub path: Option<PathBuf>,
+    pub error: Option<String>,
+    pub path_input: String, // contents of the "Open" / "Save As" path field
 }
 
 #[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
@@ -39,9 +314,100 @@ pub struct Selected(pub Option<Entity>);
 
 #[derive(Resource, Default, Clone)]
 pub struct Graph {
-    pub adj: HashMap<Entity, Vec<Entity>>,
+    pub adj: HashMap<Entity, Vec<Entity>>,    // out-neighbours
+    pub in_adj: HashMap<Entity, Vec<Entity>>, // in-neighbours; same as `adj` when undirected
     pub edges: Vec<Entity>,
     pub curr_id: usize,
+    pub directed: bool, // `adj` only holds `from -> to` for each edge
+    pub weights: HashMap<Entity, f32>, // per edge entity, so parallel edges keep their own
+}
+
+/// Removes one occurrence of `node` from `neighbours`; parallel edges put a
+/// neighbour in the list once per edge.
+fn remove_one(neighbours: Option<&mut Vec<Entity>>, node: Entity) {
+    if let Some(neighbours) = neighbours
+        && let Some(i) = neighbours.iter().position(|&n| n == node)
+    {
+        neighbours.remove(i);
+    }
+}
+
+impl Graph {
+    /// Records a new edge entity between two nodes.
+    pub fn connect(&mut self, edge: Entity, from: Entity, to: Entity, weight: f32) {
+        self.adj.entry(from).or_default().push(to);
+        self.in_adj.entry(to).or_default().push(from);
+        if !self.directed {
+            self.adj.entry(to).or_default().push(from);
+            self.in_adj.entry(from).or_default().push(to);
+        }
+        self.weights.insert(edge, weight);
+        self.edges.push(edge);
+    }
+
+    /// Records a new node without edges.
+    pub fn add_node(&mut self, node: Entity) {
+        self.adj.insert(node, Vec::new());
+        self.in_adj.insert(node, Vec::new());
+    }
+
+    /// Forgets a node and every adjacency entry that mentions it. Its edge
+    /// entities must be removed with [`Graph::disconnect`] first.
+    pub fn remove_node(&mut self, node: Entity) {
+        self.adj.remove(&node);
+        self.in_adj.remove(&node);
+        for neighbours in self.adj.values_mut().chain(self.in_adj.values_mut()) {
+            neighbours.retain(|&n| n != node);
+        }
+    }
+
+    /// Switches between directed and undirected adjacency, re-recording the
+    /// given `(edge, from, to, weight)` edges under the new mode.
+    pub fn set_directed(
+        &mut self,
+        directed: bool,
+        edges: impl IntoIterator<Item = (Entity, Entity, Entity, f32)>,
+    ) {
+        self.directed = directed;
+        for neighbours in self.adj.values_mut().chain(self.in_adj.values_mut()) {
+            neighbours.clear();
+        }
+        self.weights.clear();
+        self.edges.clear();
+        for (edge, from, to, weight) in edges {
+            self.connect(edge, from, to, weight);
+        }
+    }
+
+    /// Forgets an edge entity; the inverse of [`Graph::connect`].
+    pub fn disconnect(&mut self, edge: Entity, from: Entity, to: Entity) {
+        remove_one(self.adj.get_mut(&from), to);
+        remove_one(self.in_adj.get_mut(&to), from);
+        if !self.directed {
+            remove_one(self.adj.get_mut(&to), from);
+            remove_one(self.in_adj.get_mut(&from), to);
+        }
+        self.weights.remove(&edge);
+        self.edges.retain(|&e| e != edge);
+    }
+
+    pub fn set_weight(&mut self, edge: Entity, weight: f32) {
+        self.weights.insert(edge, weight);
+    }
+
+    /// How many edges lead from `from` to `to`, or join the two either way
+    /// round when undirected.
+    pub fn edge_count(&self, from: Entity, to: Entity) -> usize {
+        let count = (self.adj.get(&from).into_iter().flatten())
+            .filter(|&&n| n == to)
+            .count();
+        // `connect` lists an undirected self-loop twice
+        if !self.directed && from == to {
+            count / 2
+        } else {
+            count
+        }
+    }
 }
 #[derive(Resource, Default)]
 pub struct EdgeCreation {
@@ -54,26 +420,273 @@ pub struct DeletionRequest {
     pub node: Option<Entity>,
     pub edge: Option<Entity>,
 }
+
+/// What was last right-clicked in edit mode, whose popup is open.
+#[derive(Resource, Default)]
+pub struct ContextMenu {
+    pub node: Option<Entity>,
+    pub edge: Option<Entity>,
+}
 #[derive(Resource, Default)]
 pub struct LuaManager {
     pub active_script: Option<LuaThreadState>,
+    /// Where REPL lines run; its globals outlive each line.
+    pub repl: Option<LuaThreadState>,
+    /// Script commands not applied yet because they depend on a spawn or
+    /// despawn from the same frame.
+    pub pending: VecDeque<ScriptCommand>,
 }
 
 #[derive(Message, Clone)]
 pub enum ScriptCommand {
-    SetColor(u64, String),
-    ResetColor(u64),
+    // nodes are named by `GNode::id`
+    SetColor(usize, Color),
+    ResetColor(usize),
+    SetPinned(usize, bool),
+    AddNode {
+        id: usize, // handed out by the script's `add_node`
+        label: Option<String>,
+        pos: Option<Vec2>,
+    },
+    AddEdge(usize, usize, f32),
+    RemoveNode(usize),
+    RemoveEdge(usize, usize),
+    SetLabel(usize, String),
+}
+
+/// Replaces the current graph with the contents of a file.
+#[derive(Message)]
+pub struct OpenGraph {
+    pub path: PathBuf,
+}
+
+/// Lays the graph out again with the given algorithm.
+#[derive(Message, Clone, Copy)]
+pub struct RunLayout(pub LayoutKind);
+
+/// A layout running in the background, with the entities its input indices
+/// refer to.
+pub struct LayoutTask {
+    pub nodes: Vec<Entity>,
+    pub edges: Vec<Entity>,
+    pub task: Task<LayoutResult>,
+}
+
+/// A layout being computed in the background, and the animation of the
+/// nodes towards the last one computed.
+#[derive(Resource, Default)]
+pub struct LayoutState {
+    pub task: Option<LayoutTask>,
+    pub moves: Vec<(Entity, Vec2, Vec2)>, // (node, from, to)
+    pub elapsed: f32,
+    pub stopped_physics: bool, // the last layout switched the simulation off
+}
+
+/// One reversible change to the graph. Nodes are referred to by their
+/// `GNode::id`, since undoing a deletion spawns them again as new entities.
+#[derive(Clone, Debug)]
+pub enum Edit {
+    AddNode(NodeData, Vec<EdgeData>), // the node and the edges it comes back with
+    RemoveNode(NodeData, Vec<EdgeData>),
+    // with the edge's place among the edges joining the same two nodes, in
+    // the order of `Graph::edges`, which tells parallel edges apart
+    AddEdge(EdgeData, usize),
+    RemoveEdge(EdgeData, usize),
+    MoveNode {
+        id: usize,
+        from: Vec2,
+        to: Vec2,
+    },
+    SetWeight {
+        from: usize,
+        to: usize,
+        nth: usize, // place among the edges joining `from` and `to`, as above
+        old: f32,
+        new: f32,
+    },
+    Recolor(Vec<(usize, Color, Color)>), // (node, old color, new color)
+    Relabel {
+        id: usize,
+        old: String,
+        new: String,
+    },
+}
+
+impl Edit {
+    /// The edit that takes this one back.
+    pub fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::AddNode(node, edges) => Edit::RemoveNode(node, edges),
+            Edit::RemoveNode(node, edges) => Edit::AddNode(node, edges),
+            Edit::AddEdge(edge, nth) => Edit::RemoveEdge(edge, nth),
+            Edit::RemoveEdge(edge, nth) => Edit::AddEdge(edge, nth),
+            Edit::MoveNode { id, from, to } => Edit::MoveNode {
+                id,
+                from: to,
+                to: from,
+            },
+            Edit::SetWeight {
+                from,
+                to,
+                nth,
+                old,
+                new,
+            } => Edit::SetWeight {
+                from,
+                to,
+                nth,
+                old: new,
+                new: old,
+            },
+            Edit::Recolor(changes) => Edit::Recolor(
+                changes
+                    .into_iter()
+                    .map(|(id, old, new)| (id, new, old))
+                    .collect(),
+            ),
+            Edit::Relabel { id, old, new } => Edit::Relabel {
+                id,
+                old: new,
+                new: old,
+            },
+        }
+    }
+}
+
+/// Undo and redo stacks of the edits made to the current graph.
+#[derive(Resource, Default)]
+pub struct History {
+    pub undo: VecDeque<Edit>, // oldest first, dropped once over the limit
+    pub redo: Vec<Edit>,
+}
+
+impl History {
+    const LIMIT: usize = 1000;
+
+    /// Records an edit that has just been made, forgetting everything that
+    /// could have been redone.
+    pub fn push(&mut self, edit: Edit) {
+        if self.undo.len() == Self::LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(edit);
+        self.redo.clear();
+    }
+
+    /// Like [`History::push`], but folds repeated changes to the same edge's
+    /// weight (one per frame while a value is dragged) into a single edit.
+    pub fn push_weight(&mut self, from: usize, to: usize, nth: usize, old: f32, new: f32) {
+        if let Some(Edit::SetWeight {
+            from: f,
+            to: t,
+            nth: n,
+            new: last,
+            ..
+        }) = self.undo.back_mut()
+            && (*f, *t, *n) == (from, to, nth)
+        {
+            *last = new;
+            self.redo.clear();
+            return;
+        }
+        self.push(Edit::SetWeight {
+            from,
+            to,
+            nth,
+            old,
+            new,
+        });
+    }
+}
+
+#[derive(Message, Clone, Copy)]
+pub enum HistoryStep {
+    Undo,
+    Redo,
 }
 
 #[derive(Message)]
 pub struct ExecLuaScript {
+    pub name: String, // file name, for error messages
+    pub code: String,
+    pub running: bool, // step on its own from the start
+}
+
+/// A line typed into the script console, run at once against the current
+/// graph.
+#[derive(Message)]
+pub struct EvalLua {
+    pub code: String,
+}
+
+/// Contents of the script editor and the console's input line.
+#[derive(Resource)]
+pub struct ScriptEditor {
+    pub name: String, // file name in `Config::scripts_dir`
     pub code: String,
+    pub saved: String, // `code` as last opened or saved
+    /// A script opened while `code` had unsaved edits, waiting for the user
+    /// to keep the edits or drop them: (name, code).
+    pub replacing: Option<(String, String)>,
+    pub input: String,
+}
+
+impl Default for ScriptEditor {
+    fn default() -> Self {
+        ScriptEditor {
+            name: "untitled.lua".to_string(),
+            code: String::new(),
+            saved: String::new(),
+            replacing: None,
+            input: String::new(),
+        }
+    }
+}
+
+impl ScriptEditor {
+    pub fn is_modified(&self) -> bool {
+        self.code != self.saved
+    }
+
+    /// Shows `code` in the editor, dropping what was there.
+    pub fn open(&mut self, name: String, code: String) {
+        self.name = name;
+        self.saved = code.clone();
+        self.code = code;
+        self.replacing = None;
+    }
+}
+
+/// What scripts print and the errors they stop with, shown in the script
+/// console.
+#[derive(Resource, Default)]
+pub struct ScriptConsole {
+    pub lines: VecDeque<ConsoleLine>, // oldest first, dropped once over the limit
+}
+
+pub enum ConsoleLine {
+    Output(String),
+    Error(String),
+}
+
+impl ScriptConsole {
+    const LIMIT: usize = 1000;
+
+    pub fn push(&mut self, line: ConsoleLine) {
+        if self.lines.len() == Self::LIMIT {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
 }
 
 pub struct LuaThreadState {
     pub lua: Lua,
     pub thread: Thread,
     pub event_buffer: Arc<Mutex<Vec<ScriptCommand>>>,
+    pub output: Arc<Mutex<Vec<String>>>, // printed since the last step
+    pub graph: Arc<Mutex<ScriptGraph>>,  // what the script's `graph` reads
+    pub step_held: bool,                 // a step waits for earlier commands to apply
     pub running: bool,
     pub speed: f32,
 }
@@ -86,40 +699,238 @@ pub struct NodeColors {
     pub colors: HashMap<Entity, Color>,
 }
 
+/// Snapshot of the graph handed to scripts, with nodes named by their
+/// `GNode::id`.
+#[derive(Default)]
+pub struct ScriptGraph {
+    pub directed: bool,
+    pub nodes: BTreeMap<usize, ScriptNode>,
+    /// Nodes the running step has added (`true`) or removed (`false`); they
+    /// only show up in `nodes` from the next step on.
+    pub queued: HashMap<usize, bool>,
+}
+
+pub struct ScriptNode {
+    pub label: String,
+    pub pos: Vec2,
+    pub out: Vec<usize>, // out-neighbours; all neighbours when undirected
+    pub incoming: Vec<usize>,
+    pub weights: HashMap<usize, f32>, // weight of the edge to each out-neighbour
+}
+
+impl ScriptGraph {
+    pub fn new<'a>(
+        graph: &Graph,
+        nodes: impl Iterator<Item = (Entity, &'a GNode, Vec2)>,
+        edges: impl Iterator<Item = (Entity, &'a GEdge)>,
+    ) -> Self {
+        let nodes: Vec<_> = nodes.collect();
+        let ids: HashMap<Entity, usize> = nodes.iter().map(|(e, n, _)| (*e, n.id)).collect();
+        let id_list = |list: Option<&Vec<Entity>>| -> Vec<usize> {
+            list.into_iter()
+                .flatten()
+                .filter_map(|n| ids.get(n).copied())
+                .collect()
+        };
+        let mut nodes: BTreeMap<usize, ScriptNode> = nodes
+            .iter()
+            .map(|&(ent, node, pos)| {
+                let node_data = ScriptNode {
+                    label: node.label.clone(),
+                    pos,
+                    out: id_list(graph.adj.get(&ent)),
+                    incoming: id_list(graph.in_adj.get(&ent)),
+                    weights: HashMap::new(),
+                };
+                (node.id, node_data)
+            })
+            .collect();
+        // of parallel edges, the first one recorded gives the weight
+        for (ent, edge) in edges {
+            let (Some(&from), Some(&to), Some(&weight)) = (
+                ids.get(&edge.from),
+                ids.get(&edge.to),
+                graph.weights.get(&ent),
+            ) else {
+                continue;
+            };
+            let ends = if graph.directed {
+                &[(from, to)][..]
+            } else {
+                &[(from, to), (to, from)][..]
+            };
+            for &(a, b) in ends {
+                if let Some(node) = nodes.get_mut(&a) {
+                    node.weights.entry(b).or_insert(weight);
+                }
+            }
+        }
+        ScriptGraph {
+            directed: graph.directed,
+            nodes,
+            queued: HashMap::new(),
+        }
+    }
+
+    /// The node with id `id`, or a Lua error naming it.
+    pub fn node(&self, id: usize) -> mlua::Result<&ScriptNode> {
+        self.nodes
+            .get(&id)
+            .ok_or_else(|| mlua::Error::runtime(format!("no node with id {id}")))
+    }
+
+    /// A Lua error unless `id` names a node, counting the ones the running
+    /// step has added or removed.
+    pub fn check_node(&self, id: usize) -> mlua::Result<()> {
+        let exists =
+            (self.queued.get(&id).copied()).unwrap_or_else(|| self.nodes.contains_key(&id));
+        if exists {
+            Ok(())
+        } else {
+            Err(mlua::Error::runtime(format!("no node with id {id}")))
+        }
+    }
+}
+
+/// The graph as a script sees it. It is brought up to date with the world
+/// before every step and stays the same while the step runs, so a script
+/// sees its own changes from the next step on.
 #[derive(Clone)]
 pub struct LuaGraph {
-    pub inner: Arc<Mutex<Graph>>,
+    pub inner: Arc<Mutex<ScriptGraph>>,
 }
 
 impl UserData for LuaGraph {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("len", |_, this, ()| {
             let g = this.inner.lock().unwrap();
-            Ok(g.adj.len())
+            Ok(g.nodes.len())
         });
 
+        // Node ids in increasing order
         methods.add_method("get_nodes", |lua, this, ()| {
             let g = this.inner.lock().unwrap();
-            let tbl = lua.create_table()?;
-            for (i, e) in g.adj.keys().enumerate() {
-                tbl.set(i + 1, e.to_bits())?;
-            }
-            Ok(tbl)
+            lua.create_sequence_from(g.nodes.keys().copied())
         });
 
-        methods.add_method("get_neighbours", |lua, this, node: u64| {
+        methods.add_method("node", |_, this, NodeId(id)| {
+            let exists = this.inner.lock().unwrap().nodes.contains_key(&id);
+            Ok(exists.then(|| LuaNode {
+                id,
+                graph: Arc::clone(&this.inner),
+            }))
+        });
+
+        // Out-neighbours; all neighbours in an undirected graph
+        methods.add_method("get_neighbours", |lua, this, NodeId(id)| {
             let g = this.inner.lock().unwrap();
-            let tbl = lua.create_table()?;
-            for (i, n) in g
-                .adj
-                .get(&Entity::from_bits(node))
-                .unwrap()
-                .iter()
-                .enumerate()
-            {
-                tbl.set(i + 1, n.to_bits())?;
-            }
-            Ok(tbl)
-        })
+            neighbour_table(lua, &g.node(id)?.out)
+        });
+
+        methods.add_method("get_out_neighbours", |lua, this, NodeId(id)| {
+            let g = this.inner.lock().unwrap();
+            neighbour_table(lua, &g.node(id)?.out)
+        });
+
+        methods.add_method("get_in_neighbours", |lua, this, NodeId(id)| {
+            let g = this.inner.lock().unwrap();
+            neighbour_table(lua, &g.node(id)?.incoming)
+        });
+
+        methods.add_method("is_directed", |_, this, ()| {
+            Ok(this.inner.lock().unwrap().directed)
+        });
+
+        methods.add_method("get_weight", |_, this, (NodeId(from), NodeId(to))| {
+            let g = this.inner.lock().unwrap();
+            Ok(g.nodes.get(&from).and_then(|n| n.weights.get(&to)).copied())
+        });
+    }
+}
+
+fn neighbour_table(lua: &Lua, neighbours: &[usize]) -> mlua::Result<Table> {
+    lua.create_sequence_from(neighbours.iter().copied())
+}
+
+/// A node as scripts see it, from `graph:node(id)`. Its fields are read from
+/// the graph's current snapshot, so a handle kept across steps stays current.
+pub struct LuaNode {
+    pub id: usize,
+    graph: Arc<Mutex<ScriptGraph>>,
+}
+
+impl LuaNode {
+    fn read<T>(&self, f: impl FnOnce(&ScriptNode) -> T) -> Option<T> {
+        self.graph.lock().unwrap().nodes.get(&self.id).map(f)
+    }
+}
+
+impl UserData for LuaNode {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_, this| Ok(this.id));
+        // the fields are nil once the node has been deleted
+        fields.add_field_method_get("label", |_, this| Ok(this.read(|n| n.label.clone())));
+        fields.add_field_method_get("x", |_, this| Ok(this.read(|n| n.pos.x)));
+        fields.add_field_method_get("y", |_, this| Ok(this.read(|n| n.pos.y)));
+        fields.add_field_method_get("degree", |_, this| {
+            let directed = this.graph.lock().unwrap().directed;
+            Ok(this.read(|n| {
+                if directed {
+                    n.out.len() + n.incoming.len()
+                } else {
+                    n.out.len()
+                }
+            }))
+        });
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(this
+                .read(|n| n.label.clone())
+                .unwrap_or(this.id.to_string()))
+        });
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: AnyUserData| {
+            Ok(other.borrow::<LuaNode>().is_ok_and(|o| o.id == this.id))
+        });
+    }
+}
+
+/// A node passed to a script function: its id, or a handle from
+/// `graph:node`.
+pub struct NodeId(pub usize);
+
+impl FromLua for NodeId {
+    fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
+        match value {
+            Value::UserData(ud) => Ok(NodeId(ud.borrow::<LuaNode>()?.id)),
+            other => usize::from_lua(other, lua).map(NodeId),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_edges_keep_their_own_weights() {
+        let mut world = World::new();
+        let [a, b, e1, e2] = [(); 4].map(|_| world.spawn_empty().id());
+        let mut graph = Graph::default();
+        graph.add_node(a);
+        graph.add_node(b);
+        graph.connect(e1, a, b, 2.);
+        graph.connect(e2, a, b, 5.);
+        graph.set_weight(e1, 3.);
+        assert_eq!(graph.weights[&e1], 3.);
+        assert_eq!(graph.weights[&e2], 5.);
+
+        graph.disconnect(e1, a, b);
+        assert_eq!(graph.weights.get(&e1), None);
+        assert_eq!(graph.weights[&e2], 5.);
+        assert_eq!(graph.adj[&a], [b]);
+        assert_eq!(graph.adj[&b], [a]);
+        assert_eq!(graph.edges, [e2]);
     }
 }
@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::egui::text::{LayoutJob, TextFormat};
+use bevy_egui::egui::{Color32, FontId};
+use bevy_egui::{EguiContexts, egui};
+
+use crate::components::*;
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+/// Globals grephite gives scripts, plus the library they all lean on.
+const BUILTINS: &[&str] = &[
+    "graph",
+    "print",
+    "set_color",
+    "reset_color",
+    "set_pinned",
+    "add_node",
+    "add_edge",
+    "remove_node",
+    "remove_edge",
+    "set_label",
+    "coroutine",
+];
+
+const KEYWORD_COLOR: Color32 = Color32::from_rgb(198, 120, 221);
+const BUILTIN_COLOR: Color32 = Color32::from_rgb(97, 175, 239);
+const STRING_COLOR: Color32 = Color32::from_rgb(152, 195, 121);
+const NUMBER_COLOR: Color32 = Color32::from_rgb(209, 154, 102);
+const COMMENT_COLOR: Color32 = Color32::GRAY;
+
+/// The script editor window of script mode: edit a script, run or step it,
+/// and save it to the scripts directory.
+pub fn script_editor(
+    mut egui_ctx: EguiContexts,
+    mut editor: ResMut<ScriptEditor>,
+    config: Res<Config>,
+    manager: Res<LuaManager>,
+    mut run: MessageWriter<ExecLuaScript>,
+    mut step: MessageWriter<StepLua>,
+    mut console: ResMut<ScriptConsole>,
+) -> Result {
+    let editor = &mut *editor;
+    egui::Window::new("Script Editor")
+        .default_width(500.)
+        .show(egui_ctx.ctx_mut()?, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut editor.name);
+                if ui.button("Save").clicked() {
+                    match save_script(&config.scripts_dir, &editor.name, &editor.code) {
+                        Ok(path) => {
+                            editor.saved = editor.code.clone();
+                            console.push(ConsoleLine::Output(format!("Saved {}", path.display())))
+                        }
+                        Err(e) => console.push(ConsoleLine::Error(e)),
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                let script = |running| ExecLuaScript {
+                    name: editor.name.clone(),
+                    code: editor.code.clone(),
+                    running,
+                };
+                if ui
+                    .button("Run")
+                    .on_hover_text("Start this script and keep stepping it")
+                    .clicked()
+                {
+                    run.write(script(true));
+                }
+                if ui
+                    .button("Step")
+                    .on_hover_text("Step the running script, starting this one if none is")
+                    .clicked()
+                {
+                    if manager.active_script.is_none() {
+                        run.write(script(false));
+                    }
+                    step.write(StepLua);
+                }
+            });
+
+            let font = egui::TextStyle::Monospace.resolve(ui.style());
+            let text_color = ui.visuals().text_color();
+            let mut layouter = |ui: &egui::Ui, buf: &dyn egui::TextBuffer, wrap_width: f32| {
+                let mut job = highlight_lua(buf.as_str(), &font, text_color);
+                job.wrap.max_width = wrap_width;
+                ui.fonts_mut(|f| f.layout_job(job))
+            };
+            egui::ScrollArea::vertical()
+                .max_height(400.)
+                .show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut editor.code)
+                            .code_editor()
+                            .desired_rows(20)
+                            .desired_width(f32::INFINITY)
+                            .layouter(&mut layouter),
+                    );
+                });
+        });
+
+    if let Some((name, code)) = editor.replacing.clone() {
+        egui::Window::new("Unsaved changes")
+            .collapsible(false)
+            .show(egui_ctx.ctx_mut()?, |ui| {
+                ui.label(format!(
+                    "{} has unsaved changes. Replace them with {name}?",
+                    editor.name
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Keep editing").clicked() {
+                        editor.replacing = None;
+                    }
+                    if ui.button("Discard changes").clicked() {
+                        editor.open(name, code);
+                    }
+                });
+            });
+    }
+    Ok(())
+}
+
+/// Writes a script into `dir` as `name`, with `.lua` added if it is missing.
+/// `name` must be a plain file name, so scripts cannot be written outside
+/// `dir`.
+fn save_script(dir: &str, name: &str, code: &str) -> Result<PathBuf, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Give the script a file name to save it".to_string());
+    }
+    if name.contains(['/', '\\']) || name.contains("..") {
+        return Err(format!(
+            "{name:?} is not a plain file name; scripts are saved in {dir}"
+        ));
+    }
+    let path = if name.ends_with(".lua") {
+        Path::new(dir).join(name)
+    } else {
+        Path::new(dir).join(format!("{name}.lua"))
+    };
+    fs::create_dir_all(dir)
+        .and_then(|()| fs::write(&path, code))
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(path)
+}
+
+/// Lua source split into colored tokens: keywords, grephite's globals,
+/// strings, numbers and comments.
+fn highlight_lua(code: &str, font: &FontId, text_color: Color32) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    let mut rest = code;
+    while let Some(c) = rest.chars().next() {
+        let (len, color) = if rest.starts_with("--") {
+            (comment_len(rest), COMMENT_COLOR)
+        } else if rest.starts_with("[[") {
+            (long_bracket_len(rest), STRING_COLOR)
+        } else if c == '"' || c == '\'' {
+            (quoted_len(rest, c), STRING_COLOR)
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '.')
+                .unwrap_or(rest.len());
+            (len, NUMBER_COLOR)
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let color = if KEYWORDS.contains(&word) {
+                KEYWORD_COLOR
+            } else if BUILTINS.contains(&word) {
+                BUILTIN_COLOR
+            } else {
+                text_color
+            };
+            (len, color)
+        } else {
+            (c.len_utf8(), text_color)
+        };
+        job.append(&rest[..len], 0., TextFormat::simple(font.clone(), color));
+        rest = &rest[len..];
+    }
+    job
+}
+
+/// `--[[ ... ]]` up to its end, any other comment up to the end of the line.
+fn comment_len(s: &str) -> usize {
+    if s[2..].starts_with("[[") {
+        2 + long_bracket_len(&s[2..])
+    } else {
+        s.find('\n').unwrap_or(s.len())
+    }
+}
+
+/// `[[ ... ]]` up to its end, or the rest of the code if it is not closed.
+fn long_bracket_len(s: &str) -> usize {
+    s[2..].find("]]").map_or(s.len(), |end| end + 4)
+}
+
+/// A string in `quote`s, up to the closing one or the end of the line.
+fn quoted_len(s: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            '\n' => return i,
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return i + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_adds_the_extension() {
+        let dir = std::env::temp_dir().join(format!("grephite-scripts-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let path = save_script(dir, " walk ", "print(1)").unwrap();
+        assert_eq!(path, Path::new(dir).join("walk.lua"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "print(1)");
+        let path = save_script(dir, "walk.lua", "print(2)").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "print(2)");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn save_rejects_paths() {
+        for name in ["", "  ", "../x.lua", "..", "a/b.lua", "/tmp/x", "a\\b.lua"] {
+            assert!(save_script("scripts", name, "").is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn highlighting() {
+        let code = "local s = add_node(\"a -- b\") -- note\nx = 0x1F + [[raw]]";
+        let job = highlight_lua(code, &FontId::monospace(12.), Color32::WHITE);
+        let tokens: Vec<(&str, Color32)> = job
+            .sections
+            .iter()
+            .map(|s| (&code[s.byte_range.clone()], s.format.color))
+            .filter(|(text, _)| !text.trim().is_empty())
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                ("local", KEYWORD_COLOR),
+                ("s", Color32::WHITE),
+                ("=", Color32::WHITE),
+                ("add_node", BUILTIN_COLOR),
+                ("(", Color32::WHITE),
+                ("\"a -- b\"", STRING_COLOR),
+                (")", Color32::WHITE),
+                ("-- note", COMMENT_COLOR),
+                ("x", Color32::WHITE),
+                ("=", Color32::WHITE),
+                ("0x1F", NUMBER_COLOR),
+                ("+", Color32::WHITE),
+                ("[[raw]]", STRING_COLOR),
+            ]
+        );
+    }
+}
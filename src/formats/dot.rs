@@ -0,0 +1,658 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use bevy::color::Srgba;
+use bevy::math::Vec2;
+
+use super::{
+    EdgeData, GraphData, NodeData, ParseError, assign_ids, node_keys, parse_color, take_flag,
+    take_weight,
+};
+use crate::components::AttrValue;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Id(String),
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Semi,
+    Comma,
+    Colon,
+    Equals,
+    Plus,
+    Edge, // `--` or `->`
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl Lexer<'_> {
+    fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+        let mut lexer = Lexer {
+            chars: src.chars().peekable(),
+            line: 1,
+        };
+        let mut tokens = Vec::new();
+        let mut line_start = true;
+        while let Some(c) = lexer.chars.next() {
+            let line = lexer.line;
+            let token = match c {
+                '\n' => {
+                    lexer.line += 1;
+                    line_start = true;
+                    continue;
+                }
+                c if c.is_whitespace() => continue,
+                // `#` lines are C preprocessor output and ignored by Graphviz
+                '#' if line_start => {
+                    lexer.skip_line();
+                    continue;
+                }
+                '/' if lexer.chars.peek() == Some(&'/') => {
+                    lexer.skip_line();
+                    continue;
+                }
+                '/' if lexer.chars.peek() == Some(&'*') => {
+                    lexer.skip_block_comment()?;
+                    continue;
+                }
+                '{' => Token::LBrace,
+                '}' => Token::RBrace,
+                '[' => Token::LBracket,
+                ']' => Token::RBracket,
+                ';' => Token::Semi,
+                ',' => Token::Comma,
+                ':' => Token::Colon,
+                '=' => Token::Equals,
+                '+' => Token::Plus,
+                '-' if matches!(lexer.chars.peek(), Some('-') | Some('>')) => {
+                    lexer.chars.next();
+                    Token::Edge
+                }
+                '"' => Token::Id(lexer.quoted()?),
+                '<' => Token::Id(lexer.html()?),
+                c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                    let mut id = String::from(c);
+                    while let Some(&c) = lexer.chars.peek() {
+                        if c.is_alphanumeric() || c == '_' || c == '.' {
+                            id.push(c);
+                            lexer.chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    Token::Id(id)
+                }
+                c => {
+                    return Err(ParseError {
+                        line,
+                        message: format!("unexpected character `{c}`"),
+                    });
+                }
+            };
+            line_start = false;
+            tokens.push((token, line));
+        }
+        Ok(tokens)
+    }
+
+    fn skip_line(&mut self) {
+        for c in self.chars.by_ref() {
+            if c == '\n' {
+                self.line += 1;
+                break;
+            }
+        }
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), ParseError> {
+        let start = self.line;
+        self.chars.next();
+        let mut prev = ' ';
+        for c in self.chars.by_ref() {
+            if c == '\n' {
+                self.line += 1;
+            }
+            if prev == '*' && c == '/' {
+                return Ok(());
+            }
+            prev = c;
+        }
+        Err(ParseError {
+            line: start,
+            message: "unterminated comment".to_string(),
+        })
+    }
+
+    fn quoted(&mut self) -> Result<String, ParseError> {
+        let start = self.line;
+        let mut s = String::new();
+        while let Some(c) = self.chars.next() {
+            match c {
+                '"' => return Ok(s),
+                '\\' => match self.chars.next() {
+                    Some(c @ ('"' | '\\')) => s.push(c),
+                    Some('\n') => self.line += 1, // line continuation
+                    Some(c) => {
+                        s.push('\\');
+                        s.push(c);
+                    }
+                    None => break,
+                },
+                c => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    s.push(c);
+                }
+            }
+        }
+        Err(ParseError {
+            line: start,
+            message: "unterminated string".to_string(),
+        })
+    }
+
+    fn html(&mut self) -> Result<String, ParseError> {
+        let start = self.line;
+        let mut s = String::new();
+        let mut depth = 1;
+        for c in self.chars.by_ref() {
+            match c {
+                '<' => depth += 1,
+                '>' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(s);
+                    }
+                }
+                '\n' => self.line += 1,
+                _ => {}
+            }
+            s.push(c);
+        }
+        Err(ParseError {
+            line: start,
+            message: "unterminated HTML string".to_string(),
+        })
+    }
+}
+
+type Attrs = BTreeMap<String, String>;
+
+/// Default attributes set by `node [...]` / `edge [...]` statements, scoped
+/// to the enclosing (sub)graph.
+#[derive(Clone, Default)]
+struct Scope {
+    node: Attrs,
+    edge: Attrs,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    directed: bool,
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+    node_attrs: Vec<Attrs>,
+    edges: Vec<(usize, usize, Attrs, usize)>, // with the line of the attributes
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, l)| *l)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line(),
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+        self.pos += 1;
+        t
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), ParseError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {what}")))
+        }
+    }
+
+    fn keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(Token::Id(id)) if id.eq_ignore_ascii_case(kw))
+    }
+
+    /// Reads an ID, joining `"a" + "b"` concatenations.
+    fn id(&mut self) -> Result<String, ParseError> {
+        match self.next() {
+            Some(Token::Id(mut id)) => {
+                while self.eat(&Token::Plus) {
+                    match self.next() {
+                        Some(Token::Id(more)) => id.push_str(&more),
+                        _ => return Err(self.error("expected a string after `+`")),
+                    }
+                }
+                Ok(id)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected an identifier"))
+            }
+        }
+    }
+
+    fn graph(&mut self) -> Result<(), ParseError> {
+        if self.keyword("strict") {
+            self.pos += 1;
+        }
+        if self.keyword("digraph") {
+            self.directed = true;
+        } else if !self.keyword("graph") {
+            return Err(self.error("expected `graph` or `digraph`"));
+        }
+        self.pos += 1;
+        if matches!(self.peek(), Some(Token::Id(_))) {
+            self.id()?;
+        }
+        self.expect(Token::LBrace, "`{`")?;
+        self.stmt_list(&mut Scope::default())?;
+        self.expect(Token::RBrace, "`}`")?;
+        if self.pos < self.tokens.len() {
+            return Err(self.error("unexpected content after the graph"));
+        }
+        Ok(())
+    }
+
+    /// Parses statements up to the closing `}` and returns the nodes they
+    /// mention, which is what an edge to a subgraph connects to.
+    fn stmt_list(&mut self, scope: &mut Scope) -> Result<Vec<usize>, ParseError> {
+        let mut members = Vec::new();
+        while !matches!(self.peek(), Some(Token::RBrace) | None) {
+            self.stmt(scope, &mut members)?;
+            self.eat(&Token::Semi);
+        }
+        Ok(members)
+    }
+
+    fn stmt(&mut self, scope: &mut Scope, members: &mut Vec<usize>) -> Result<(), ParseError> {
+        let followed_by = |p: &Parser, t: &Token| p.tokens.get(p.pos + 1).map(|t| &t.0) == Some(t);
+
+        for kw in ["node", "edge", "graph"] {
+            if self.keyword(kw) && followed_by(self, &Token::LBracket) {
+                self.pos += 1;
+                let attrs = self.attr_lists()?;
+                match kw {
+                    "node" => scope.node.extend(attrs),
+                    "edge" => scope.edge.extend(attrs),
+                    _ => {}
+                }
+                return Ok(());
+            }
+        }
+
+        // `ID = ID` graph attribute
+        if matches!(self.peek(), Some(Token::Id(_))) && followed_by(self, &Token::Equals) {
+            self.id()?;
+            self.pos += 1;
+            self.id()?;
+            return Ok(());
+        }
+
+        let mut group = self.operand(scope)?;
+        members.extend(&group);
+
+        if self.peek() != Some(&Token::Edge) {
+            // node statement
+            let attrs = self.attr_lists()?;
+            for &n in &group {
+                self.node_attrs[n].extend(attrs.clone());
+            }
+            return Ok(());
+        }
+
+        let mut chain = vec![group];
+        while self.eat(&Token::Edge) {
+            group = self.operand(scope)?;
+            members.extend(&group);
+            chain.push(group);
+        }
+        let mut attrs = scope.edge.clone();
+        let line = self.line();
+        attrs.extend(self.attr_lists()?);
+        for pair in chain.windows(2) {
+            for &from in &pair[0] {
+                for &to in &pair[1] {
+                    self.edges.push((from, to, attrs.clone(), line));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// An edge operand: a node id, or a subgraph standing for all its nodes.
+    fn operand(&mut self, scope: &Scope) -> Result<Vec<usize>, ParseError> {
+        if self.keyword("subgraph") || self.peek() == Some(&Token::LBrace) {
+            if self.keyword("subgraph") {
+                self.pos += 1;
+                if matches!(self.peek(), Some(Token::Id(_))) {
+                    self.id()?;
+                }
+            }
+            self.expect(Token::LBrace, "`{`")?;
+            let mut inner = scope.clone();
+            let members = self.stmt_list(&mut inner)?;
+            self.expect(Token::RBrace, "`}`")?;
+            return Ok(members);
+        }
+
+        let name = self.id()?;
+        // ports only affect where Graphviz attaches the edge
+        while self.eat(&Token::Colon) {
+            self.id()?;
+        }
+        let n = match self.index.get(&name) {
+            Some(&n) => n,
+            None => {
+                self.index.insert(name.clone(), self.names.len());
+                self.names.push(name);
+                self.node_attrs.push(scope.node.clone());
+                self.names.len() - 1
+            }
+        };
+        Ok(vec![n])
+    }
+
+    fn attr_lists(&mut self) -> Result<Attrs, ParseError> {
+        let mut attrs = Attrs::new();
+        while self.eat(&Token::LBracket) {
+            while !self.eat(&Token::RBracket) {
+                let key = self.id()?;
+                self.expect(Token::Equals, "`=`")?;
+                let value = self.id()?;
+                attrs.insert(key, value);
+                if !self.eat(&Token::Comma) {
+                    self.eat(&Token::Semi);
+                }
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// Parses a Graphviz DOT file.
+///
+/// `label`, `pos` and `color` (or `fillcolor`) set the node's label,
+/// position and color, `pin=true` (or a `pos` ending in `!`, as in neato)
+/// pins the node, and a numeric `weight` sets the edge weight; every other
+/// node and edge attribute is kept as is.
+/// Subgraphs are flattened, with `node [...]`/`edge [...]` defaults applied
+/// within the subgraph that sets them.
+pub fn parse(content: &str) -> Result<GraphData, ParseError> {
+    let mut parser = Parser {
+        tokens: Lexer::tokenize(content)?,
+        pos: 0,
+        directed: false,
+        names: Vec::new(),
+        index: HashMap::new(),
+        node_attrs: Vec::new(),
+        edges: Vec::new(),
+    };
+    parser.graph()?;
+
+    let names: Vec<&str> = parser.names.iter().map(String::as_str).collect();
+    let ids = assign_ids(&names);
+    let mut data = GraphData {
+        directed: parser.directed,
+        ..Default::default()
+    };
+
+    for ((name, mut attrs), &id) in parser.names.iter().zip(parser.node_attrs).zip(&ids) {
+        let mut node = NodeData::new(id);
+        node.label = attrs.remove("label").unwrap_or_else(|| name.clone());
+        if let Some(pos) = attrs.remove("pos") {
+            node.pinned = pos.ends_with('!');
+            node.pos = parse_pos(pos.trim_end_matches('!'));
+        }
+        if let Some(fill) = attrs.remove("fillcolor") {
+            node.color = parse_color(&fill);
+            if attrs.get("style").is_some_and(|s| s == "filled") {
+                attrs.remove("style");
+            }
+        } else if let Some(color) = attrs.remove("color") {
+            node.color = parse_color(&color);
+        }
+        node.attrs = to_attr_values(attrs);
+        node.pinned |= take_flag(&mut node.attrs, "pin");
+        data.nodes.push(node);
+    }
+
+    for (from, to, attrs, line) in parser.edges {
+        let mut edge = EdgeData::new(ids[from], ids[to]);
+        edge.attrs = to_attr_values(attrs);
+        edge.weight =
+            take_weight(&mut edge.attrs).map_err(|message| ParseError { line, message })?;
+        data.edges.push(edge);
+    }
+    Ok(data)
+}
+
+/// Writes `data` as a DOT graph, with node colors, positions and pins as
+/// `fillcolor`, `pos` and `pin` attributes. Black nodes are left unfilled.
+pub fn write(data: &GraphData) -> String {
+    let mut out = String::new();
+    let (kind, op) = if data.directed {
+        ("digraph", "->")
+    } else {
+        ("graph", "--")
+    };
+    let _ = writeln!(out, "{kind} {{");
+
+    let keys = node_keys(data);
+    let mut names = HashMap::new();
+    for (node, key) in data.nodes.iter().zip(&keys) {
+        names.insert(node.id, key);
+        let mut attrs: Vec<(&str, String)> = Vec::new();
+        if node.label != *key {
+            attrs.push(("label", node.label.clone()));
+        }
+        // black is what every node starts with; filling it would hide the
+        // label
+        if let Some(color) = node.color.filter(|c| c.to_srgba() != Srgba::BLACK) {
+            attrs.push(("style", "filled".to_string()));
+            attrs.push(("fillcolor", color.to_srgba().to_hex()));
+        }
+        if let Some(pos) = node.pos {
+            attrs.push(("pos", format!("{},{}", pos.x, pos.y)));
+        }
+        if node.pinned {
+            attrs.push(("pin", "true".to_string()));
+        }
+        attrs.extend(node.attrs.iter().map(|(k, v)| (k.as_str(), v.to_string())));
+        let _ = writeln!(out, "  {}{};", quote(key), attr_list(&attrs));
+    }
+
+    for edge in &data.edges {
+        let (Some(from), Some(to)) = (names.get(&edge.from), names.get(&edge.to)) else {
+            continue;
+        };
+        let attrs: Vec<(String, String)> = edge
+            .attrs_with_weight()
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string()))
+            .collect();
+        let _ = writeln!(
+            out,
+            "  {} {op} {}{};",
+            quote(from),
+            quote(to),
+            attr_list(&attrs)
+        );
+    }
+    let _ = writeln!(out, "}}");
+    out
+}
+
+fn attr_list<K: AsRef<str>>(attrs: &[(K, String)]) -> String {
+    if attrs.is_empty() {
+        return String::new();
+    }
+    let items: Vec<String> = attrs
+        .iter()
+        .map(|(k, v)| format!("{}={}", quote(k.as_ref()), quote(v)))
+        .collect();
+    format!(" [{}]", items.join(", "))
+}
+
+/// Quotes `s` unless it is already a valid bare DOT identifier.
+fn quote(s: &str) -> String {
+    let bare = s
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !["node", "edge", "graph", "digraph", "subgraph", "strict"]
+            .iter()
+            .any(|kw| s.eq_ignore_ascii_case(kw));
+    if bare {
+        s.to_string()
+    } else {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Reads a `"x,y"` position; a trailing `!` (pinned in neato) is ignored.
+fn parse_pos(s: &str) -> Option<Vec2> {
+    let (x, y) = s.trim().trim_end_matches('!').split_once(',')?;
+    Some(Vec2::new(x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+fn to_attr_values(attrs: Attrs) -> BTreeMap<String, AttrValue> {
+    attrs
+        .into_iter()
+        .map(|(k, v)| (k, AttrValue::Str(v)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::color::Color;
+
+    use super::*;
+    use crate::formats::tests::sample;
+
+    #[test]
+    fn round_trip() {
+        let mut data = sample();
+        data.nodes[3].label = "say \"hi\"".to_string();
+        data.nodes[0]
+            .attrs
+            .insert("shape".to_string(), AttrValue::Str("box".to_string()));
+        assert_eq!(parse(&write(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn backslashes_round_trip() {
+        let mut data = sample();
+        data.nodes[1].label = "C:\\dir\\".to_string();
+        data.nodes[2].label = "a\\\"b".to_string();
+        // non-numeric ids are numbered anew, so compare the labels
+        let labels = |data: &GraphData| -> Vec<String> {
+            data.nodes.iter().map(|n| n.label.clone()).collect()
+        };
+        assert_eq!(labels(&parse(&write(&data)).unwrap()), labels(&data));
+    }
+
+    #[test]
+    fn black_nodes_are_not_filled() {
+        let mut data = sample();
+        for node in &mut data.nodes {
+            node.color = Some(Color::BLACK);
+        }
+        let out = write(&data);
+        assert!(!out.contains("fillcolor"), "{out}");
+        assert!(!out.contains("filled"), "{out}");
+    }
+
+    #[test]
+    fn subgraphs_and_quoted_ids() {
+        let data = parse(
+            r#"digraph "my graph" {
+  node [color=red];
+  subgraph cluster_a {
+    node [shape=box];
+    a -> "b c";
+  }
+  "d \"e\"" -> { a; "b c" } [weight=3];
+  "f" + "g";
+  a -> f;
+}"#,
+        )
+        .unwrap();
+        let labels: Vec<&str> = data.nodes.iter().map(|n| n.label.as_str()).collect();
+        assert_eq!(labels, ["a", "b c", "d \"e\"", "fg", "f"]);
+        assert!(data.directed);
+        // defaults apply inside the subgraph that sets them and below
+        assert_eq!(
+            data.nodes[0].attrs.get("shape"),
+            Some(&AttrValue::Str("box".into()))
+        );
+        assert_eq!(data.nodes[2].attrs.get("shape"), None);
+        assert!(data.nodes.iter().all(|n| n.color == parse_color("red")));
+
+        let edges: Vec<(usize, usize, f32)> = data
+            .edges
+            .iter()
+            .map(|e| (e.from, e.to, e.weight))
+            .collect();
+        let id = |label: &str| data.nodes.iter().find(|n| n.label == label).unwrap().id;
+        assert_eq!(
+            edges,
+            [
+                (id("a"), id("b c"), 1.),
+                (id("d \"e\""), id("a"), 3.),
+                (id("d \"e\""), id("b c"), 3.),
+                (id("a"), id("f"), 1.),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_statement_is_reported() {
+        let err = parse("graph {\n  a -- b;\n  c -- ;\n}\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "expected an identifier");
+    }
+
+    #[test]
+    fn non_finite_weight_is_reported() {
+        let err = parse("graph {\n  a -- b;\n  b -- c [weight=nan];\n}\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "invalid weight `nan`");
+    }
+}
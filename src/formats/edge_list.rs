@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use bevy::math::Vec2;
+
+use super::{EdgeData, GraphData, NodeData, ParseError};
+
+/// Parses a whitespace separated edge list, one `from to [weight]` edge per
+/// line; edges without a weight get a weight of 1.
+///
+/// A line holding a single id declares a node without edges, a
+/// `#pos <id> <x> <y>` line stores the layout position of a node, a
+/// `#pin <id>` line pins a node in place, and a `#directed` line makes every
+/// edge point from `from` to `to`. Other lines starting with `#` and blank
+/// lines are ignored, so files written by [`write`] stay readable by tools
+/// that only understand plain edge lists.
+pub fn parse(content: &str) -> Result<GraphData, ParseError> {
+    let mut data = GraphData::default();
+    let mut index: HashMap<usize, usize> = HashMap::new();
+
+    let mut node = |data: &mut GraphData, id: usize| {
+        *index.entry(id).or_insert_with(|| {
+            data.nodes.push(NodeData::new(id));
+            data.nodes.len() - 1
+        })
+    };
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line == "#directed" {
+            data.directed = true;
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("#pin ") {
+            let id = parse_id(rest.trim(), i + 1)?;
+            let n = node(&mut data, id);
+            data.nodes[n].pinned = true;
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("#pos ") {
+            let parts: Vec<&str> = rest.split_whitespace().collect();
+            if parts.len() != 3 {
+                return Err(ParseError {
+                    line: i + 1,
+                    message: "expected `#pos <id> <x> <y>`".to_string(),
+                });
+            }
+            let id = parse_id(parts[0], i + 1)?;
+            let pos = Vec2::new(parse_coord(parts[1], i + 1)?, parse_coord(parts[2], i + 1)?);
+            let n = node(&mut data, id);
+            data.nodes[n].pos = Some(pos);
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let from = parse_id(parts[0], i + 1)?;
+        node(&mut data, from);
+        if parts.len() < 2 {
+            continue;
+        }
+        let to = parse_id(parts[1], i + 1)?;
+        node(&mut data, to);
+        let mut edge = EdgeData::new(from, to);
+        if let Some(w) = parts.get(2) {
+            // `nan` and `inf` parse, but would wreck the simulation
+            edge.weight = w
+                .parse()
+                .ok()
+                .filter(|w: &f32| w.is_finite())
+                .ok_or_else(|| ParseError {
+                    line: i + 1,
+                    message: format!("invalid weight `{w}`"),
+                })?;
+        }
+        data.edges.push(edge);
+    }
+    Ok(data)
+}
+
+/// Writes `data` in the format read by [`parse`].
+pub fn write(data: &GraphData) -> String {
+    let mut out = String::from("# grephite edge list\n");
+    if data.directed {
+        out.push_str("#directed\n");
+    }
+
+    for node in &data.nodes {
+        if let Some(pos) = node.pos {
+            let _ = writeln!(out, "#pos {} {} {}", node.id, pos.x, pos.y);
+        }
+        if node.pinned {
+            let _ = writeln!(out, "#pin {}", node.id);
+        }
+    }
+
+    let connected: HashSet<usize> = data.edges.iter().flat_map(|e| [e.from, e.to]).collect();
+    for node in &data.nodes {
+        if !connected.contains(&node.id) {
+            let _ = writeln!(out, "{}", node.id);
+        }
+    }
+
+    for edge in &data.edges {
+        if edge.weight == 1. {
+            let _ = writeln!(out, "{} {}", edge.from, edge.to);
+        } else {
+            let _ = writeln!(out, "{} {} {}", edge.from, edge.to, edge.weight);
+        }
+    }
+    out
+}
+
+fn parse_id(token: &str, line: usize) -> Result<usize, ParseError> {
+    token.parse().map_err(|_| ParseError {
+        line,
+        message: format!("invalid node id `{token}`"),
+    })
+}
+
+fn parse_coord(token: &str, line: usize) -> Result<f32, ParseError> {
+    token.parse().map_err(|_| ParseError {
+        line,
+        message: format!("invalid coordinate `{token}`"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::tests::{sample, without_colors};
+
+    #[test]
+    fn round_trip() {
+        let data = sample();
+        assert_eq!(parse(&write(&data)).unwrap(), without_colors(data));
+    }
+
+    #[test]
+    fn comments_starting_with_pos_are_skipped() {
+        let data = parse("#position of the root\n1 2\n").unwrap();
+        assert_eq!(data.nodes.len(), 2);
+        assert_eq!(data.nodes[0].pos, None);
+    }
+
+    #[test]
+    fn bad_line_is_reported() {
+        let err = parse("1 2\n#pos 1 0 0\n2 x\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "invalid node id `x`");
+    }
+
+    #[test]
+    fn non_finite_weights_are_rejected() {
+        for w in ["nan", "inf", "-inf"] {
+            let err = parse(&format!("1 2 1.5\n2 3 {w}\n")).unwrap_err();
+            assert_eq!(err.line, 2);
+            assert_eq!(err.message, format!("invalid weight `{w}`"));
+        }
+    }
+}
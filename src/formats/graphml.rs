@@ -0,0 +1,364 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use bevy::math::Vec2;
+use roxmltree::{Document, Node};
+
+use super::{
+    EdgeData, GraphData, NodeData, ParseError, assign_ids, node_keys, take_flag, take_weight,
+};
+use crate::components::AttrValue;
+
+const NS: &str = "http://graphml.graphdrawing.org/xmlns";
+
+/// A `<key>` declaration: which attribute a `<data key="...">` element sets.
+#[derive(Clone)]
+struct Key {
+    name: String,
+    ty: String,
+    default: Option<String>,
+}
+
+/// Parses a GraphML document.
+///
+/// Only the first `<graph>` is read and nested graphs are ignored. `<data>`
+/// values become node and edge attributes named after their key's
+/// `attr.name`, except the numeric node attributes `x` and `y`, which give
+/// the node's position, the boolean node attribute `pinned`, and the numeric
+/// edge attribute `weight`.
+pub fn parse(content: &str) -> Result<GraphData, ParseError> {
+    let doc = Document::parse(content).map_err(|e| ParseError {
+        line: e.pos().row as usize,
+        message: e.to_string(),
+    })?;
+    let root = doc.root_element();
+    if !root.has_tag_name("graphml") {
+        return Err(error(&root, "expected a <graphml> document"));
+    }
+
+    let mut node_decls = HashMap::new();
+    let mut edge_decls = HashMap::new();
+    for key in root.children().filter(|n| n.has_tag_name("key")) {
+        let id = required(&key, "id")?;
+        let decl = Key {
+            name: key.attribute("attr.name").unwrap_or(id).to_string(),
+            ty: key.attribute("attr.type").unwrap_or("string").to_string(),
+            default: key
+                .children()
+                .find(|n| n.has_tag_name("default"))
+                .and_then(|n| n.text())
+                .map(str::to_string),
+        };
+        match key.attribute("for").unwrap_or("all") {
+            "node" => {
+                node_decls.insert(id, decl);
+            }
+            "edge" => {
+                edge_decls.insert(id, decl);
+            }
+            "all" => {
+                node_decls.insert(id, decl.clone());
+                edge_decls.insert(id, decl);
+            }
+            _ => {}
+        }
+    }
+
+    let Some(graph) = root.children().find(|n| n.has_tag_name("graph")) else {
+        return Err(error(&root, "missing <graph> element"));
+    };
+    let mut data = GraphData {
+        directed: graph.attribute("edgedefault") == Some("directed"),
+        ..Default::default()
+    };
+
+    let nodes: Vec<Node> = graph
+        .children()
+        .filter(|n| n.has_tag_name("node"))
+        .collect();
+    let mut names = Vec::with_capacity(nodes.len());
+    for node in &nodes {
+        names.push(required(node, "id")?);
+    }
+    let ids = assign_ids(&names);
+    let mut index = HashMap::new();
+
+    for ((node, &name), id) in nodes.iter().zip(&names).zip(ids) {
+        if index.insert(name, id).is_some() {
+            return Err(error(node, &format!("duplicate node id `{name}`")));
+        }
+        let mut attrs = read_data(node, &node_decls)?;
+        let x = attrs.get("x").and_then(AttrValue::as_f64);
+        let y = attrs.get("y").and_then(AttrValue::as_f64);
+        let pos = match (x, y) {
+            (Some(x), Some(y)) => {
+                attrs.remove("x");
+                attrs.remove("y");
+                Some(Vec2::new(x as f32, y as f32))
+            }
+            _ => None,
+        };
+        data.nodes.push(NodeData {
+            id,
+            label: name.to_string(),
+            pos,
+            color: None,
+            pinned: take_flag(&mut attrs, "pinned"),
+            attrs,
+        });
+    }
+
+    for edge in graph.children().filter(|n| n.has_tag_name("edge")) {
+        let endpoint = |attr: &str| {
+            let name = required(&edge, attr)?;
+            index
+                .get(name)
+                .copied()
+                .ok_or_else(|| error(&edge, &format!("unknown node `{name}`")))
+        };
+        let from = endpoint("source")?;
+        let to = endpoint("target")?;
+        let mut attrs = read_data(&edge, &edge_decls)?;
+        data.edges.push(EdgeData {
+            from,
+            to,
+            weight: take_weight(&mut attrs).map_err(|e| error(&edge, &e))?,
+            attrs,
+        });
+    }
+    Ok(data)
+}
+
+/// Writes `data` as a GraphML document, declaring a key for every attribute
+/// in use and storing node positions in `x`/`y` keys, pinned nodes in a
+/// `pinned` key and edge weights in a `weight` key.
+pub fn write(data: &GraphData) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(out, r#"<graphml xmlns="{NS}">"#);
+
+    let mut node_types = declared_types(data.nodes.iter().map(|n| &n.attrs));
+    if data.nodes.iter().any(|n| n.pos.is_some()) {
+        node_types.insert("x", "double");
+        node_types.insert("y", "double");
+    }
+    if data.nodes.iter().any(|n| n.pinned) {
+        node_types.insert("pinned", "boolean");
+    }
+    let edge_attrs: Vec<_> = data.edges.iter().map(EdgeData::attrs_with_weight).collect();
+    let edge_types = declared_types(edge_attrs.iter());
+
+    let mut node_key_ids = HashMap::new();
+    let mut edge_key_ids = HashMap::new();
+    let mut next_key = 0;
+    for (domain, types, ids) in [
+        ("node", &node_types, &mut node_key_ids),
+        ("edge", &edge_types, &mut edge_key_ids),
+    ] {
+        for (name, ty) in types {
+            let id = format!("d{next_key}");
+            next_key += 1;
+            let _ = writeln!(
+                out,
+                r#"  <key id="{id}" for="{domain}" attr.name="{}" attr.type="{ty}"/>"#,
+                escape(name)
+            );
+            ids.insert(*name, id);
+        }
+    }
+
+    let edgedefault = if data.directed {
+        "directed"
+    } else {
+        "undirected"
+    };
+    let _ = writeln!(out, r#"  <graph id="G" edgedefault="{edgedefault}">"#);
+
+    let keys = node_keys(data);
+    let mut names = HashMap::new();
+    for (node, key) in data.nodes.iter().zip(&keys) {
+        names.insert(node.id, key.as_str());
+        let mut values: Vec<(&str, String)> = node
+            .attrs
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.to_string()))
+            .collect();
+        if let Some(pos) = node.pos {
+            values.push(("x", pos.x.to_string()));
+            values.push(("y", pos.y.to_string()));
+        }
+        if node.pinned {
+            values.push(("pinned", "true".to_string()));
+        }
+        write_element(&mut out, "node", &[("id", key)], &values, &node_key_ids);
+    }
+
+    for (i, (edge, attrs)) in data.edges.iter().zip(&edge_attrs).enumerate() {
+        let values: Vec<(&str, String)> = attrs
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.to_string()))
+            .collect();
+        let id = format!("e{i}");
+        let (Some(source), Some(target)) = (names.get(&edge.from), names.get(&edge.to)) else {
+            continue;
+        };
+        write_element(
+            &mut out,
+            "edge",
+            &[("id", &id), ("source", source), ("target", target)],
+            &values,
+            &edge_key_ids,
+        );
+    }
+
+    let _ = writeln!(out, "  </graph>");
+    let _ = writeln!(out, "</graphml>");
+    out
+}
+
+fn write_element(
+    out: &mut String,
+    tag: &str,
+    attrs: &[(&str, &str)],
+    values: &[(&str, String)],
+    key_ids: &HashMap<&str, String>,
+) {
+    let _ = write!(out, "    <{tag}");
+    for (name, value) in attrs {
+        let _ = write!(out, r#" {name}="{}""#, escape(value));
+    }
+    if values.is_empty() {
+        let _ = writeln!(out, "/>");
+        return;
+    }
+    let _ = writeln!(out, ">");
+    for (name, value) in values {
+        let _ = writeln!(
+            out,
+            r#"      <data key="{}">{}</data>"#,
+            key_ids[name],
+            escape(value)
+        );
+    }
+    let _ = writeln!(out, "    </{tag}>");
+}
+
+/// Chooses a GraphML type for each attribute name from the values it takes.
+fn declared_types<'a>(
+    attrs: impl Iterator<Item = &'a BTreeMap<String, AttrValue>>,
+) -> BTreeMap<&'a str, &'static str> {
+    let mut types = BTreeMap::new();
+    for map in attrs {
+        for (name, value) in map {
+            let ty = match value {
+                AttrValue::Bool(_) => "boolean",
+                AttrValue::Int(_) => "long",
+                AttrValue::Float(_) => "double",
+                AttrValue::Str(_) | AttrValue::List(_) | AttrValue::Map(_) => "string",
+            };
+            types
+                .entry(name.as_str())
+                .and_modify(|prev: &mut &str| {
+                    *prev = match (*prev, ty) {
+                        (a, b) if a == b => a,
+                        ("long", "double") | ("double", "long") => "double",
+                        _ => "string",
+                    }
+                })
+                .or_insert(ty);
+        }
+    }
+    types
+}
+
+fn read_data(
+    element: &Node,
+    keys: &HashMap<&str, Key>,
+) -> Result<BTreeMap<String, AttrValue>, ParseError> {
+    let mut attrs = BTreeMap::new();
+    for key in keys.values() {
+        if let Some(default) = &key.default {
+            attrs.insert(key.name.clone(), typed(element, &key.ty, default)?);
+        }
+    }
+    for data in element.children().filter(|n| n.has_tag_name("data")) {
+        let id = required(&data, "key")?;
+        let Some(key) = keys.get(id) else {
+            return Err(error(&data, &format!("undeclared key `{id}`")));
+        };
+        let text = data.text().unwrap_or("");
+        attrs.insert(key.name.clone(), typed(&data, &key.ty, text)?);
+    }
+    Ok(attrs)
+}
+
+fn typed(element: &Node, ty: &str, text: &str) -> Result<AttrValue, ParseError> {
+    let text = text.trim();
+    let invalid = || error(element, &format!("invalid {ty} value `{text}`"));
+    Ok(match ty {
+        "boolean" => AttrValue::Bool(text.parse().map_err(|_| invalid())?),
+        "int" | "long" => AttrValue::Int(text.parse().map_err(|_| invalid())?),
+        "float" | "double" => AttrValue::Float(text.parse().map_err(|_| invalid())?),
+        _ => AttrValue::Str(text.to_string()),
+    })
+}
+
+fn required<'a>(element: &Node<'a, '_>, attr: &str) -> Result<&'a str, ParseError> {
+    element.attribute(attr).ok_or_else(|| {
+        error(
+            element,
+            &format!("<{}> is missing `{attr}`", element.tag_name().name()),
+        )
+    })
+}
+
+fn error(element: &Node, message: &str) -> ParseError {
+    ParseError {
+        line: element.document().text_pos_at(element.range().start).row as usize,
+        message: message.to_string(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::tests::{sample, without_colors};
+
+    #[test]
+    fn round_trip() {
+        let mut data = sample();
+        data.nodes[2].label = "three & <more>".to_string();
+        data.nodes[2]
+            .attrs
+            .insert("group".to_string(), AttrValue::Int(7));
+        data.edges[1]
+            .attrs
+            .insert("since".to_string(), AttrValue::Str("2019".to_string()));
+        // labels become ids, and numeric ids come back as the same node id
+        let back = parse(&write(&data)).unwrap();
+        data.nodes[2].id = 5;
+        data.edges[1].from = 5;
+        assert_eq!(back, without_colors(data));
+    }
+
+    #[test]
+    fn bad_element_is_reported() {
+        let content = r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <graph edgedefault="undirected">
+    <node id="a"/>
+    <node id="b"><data key="missing">1</data></node>
+    <edge source="a" target="b"/>
+  </graph>
+</graphml>"#;
+        let err = parse(content).unwrap_err();
+        assert_eq!(err.line, 4);
+        assert_eq!(err.message, "undeclared key `missing`");
+    }
+}
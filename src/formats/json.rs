@@ -0,0 +1,305 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::math::Vec2;
+use serde_json::{Map, Number, Value};
+
+use super::{
+    EdgeData, GraphData, LoadError, NodeData, ParseError, assign_ids, node_keys, parse_color,
+    take_flag, take_weight,
+};
+use crate::components::AttrValue;
+
+/// Parses node-link JSON, as written by networkx's `node_link_data` and read
+/// by d3: `{"directed": false, "nodes": [{"id": 1}], "links": [{"source": 1,
+/// "target": 2}]}`. `edges` is accepted in place of `links`.
+///
+/// Link endpoints name node ids, or positions in `nodes` when they are
+/// integers that match no id (the d3 convention). The node members `label`,
+/// `color` and `x`/`y` (or `pos: [x, y]`) set the node's label, color and
+/// position, `pinned: true` or d3's fixed position `fx`/`fy` pin the node,
+/// and a numeric link `weight` sets the edge weight; all other node and link
+/// members are kept as attributes.
+pub fn parse(content: &str) -> Result<GraphData, LoadError> {
+    let root: Value = serde_json::from_str(content).map_err(|e| {
+        // serde_json appends "at line L column C"; only the column is news
+        let message = e.to_string();
+        let message = message
+            .rsplit_once(" at line ")
+            .map_or(message.as_str(), |(m, _)| m);
+        ParseError {
+            line: e.line(),
+            message: format!("{message} (column {})", e.column()),
+        }
+    })?;
+    let Value::Object(root) = root else {
+        return Err(invalid("expected a JSON object"));
+    };
+
+    let mut data = GraphData {
+        directed: root
+            .get("directed")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        ..Default::default()
+    };
+
+    let nodes = match root.get("nodes") {
+        Some(Value::Array(nodes)) => nodes.as_slice(),
+        Some(_) => return Err(invalid("`nodes` must be an array")),
+        None => &[],
+    };
+    let mut keys = Vec::with_capacity(nodes.len());
+    for (i, node) in nodes.iter().enumerate() {
+        let Value::Object(node) = node else {
+            return Err(invalid(format!("nodes[{i}] must be an object")));
+        };
+        keys.push(match node.get("id") {
+            Some(id) => key(id)
+                .ok_or_else(|| invalid(format!("nodes[{i}]: `id` must be a string or a number")))?,
+            None => i.to_string(),
+        });
+    }
+    let names: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let ids = assign_ids(&names);
+
+    let mut index = HashMap::new();
+    for (i, (node, name)) in nodes.iter().zip(&keys).enumerate() {
+        if index.insert(name.as_str(), ids[i]).is_some() {
+            return Err(invalid(format!("nodes[{i}]: duplicate id `{name}`")));
+        }
+        let mut members = node.as_object().cloned().unwrap_or_default();
+        members.remove("id");
+
+        let mut out = NodeData::new(ids[i]);
+        out.label = match members.remove("label") {
+            Some(Value::String(label)) => label,
+            Some(other) => other.to_string(),
+            None => name.clone(),
+        };
+        out.color = match members.remove("color") {
+            Some(Value::String(c)) => parse_color(&c),
+            Some(other) => {
+                members.insert("color".to_string(), other);
+                None
+            }
+            None => None,
+        };
+        if let (Some(fx), Some(fy)) = (
+            members.get("fx").and_then(Value::as_f64),
+            members.get("fy").and_then(Value::as_f64),
+        ) {
+            members.remove("fx");
+            members.remove("fy");
+            out.pos = Some(Vec2::new(fx as f32, fy as f32));
+            out.pinned = true;
+        }
+        out.pos = take_pos(&mut members).or(out.pos);
+        out.attrs = attributes(members);
+        out.pinned |= take_flag(&mut out.attrs, "pinned");
+        data.nodes.push(out);
+    }
+
+    let links = match root.get("links").or_else(|| root.get("edges")) {
+        Some(Value::Array(links)) => links.as_slice(),
+        Some(_) => return Err(invalid("`links` must be an array")),
+        None => &[],
+    };
+    for (i, link) in links.iter().enumerate() {
+        let Value::Object(link) = link else {
+            return Err(invalid(format!("links[{i}] must be an object")));
+        };
+        let mut members = link.clone();
+        let mut endpoint = |name: &str| {
+            let value = members
+                .remove(name)
+                .ok_or_else(|| invalid(format!("links[{i}]: missing `{name}`")))?;
+            key(&value)
+                .and_then(|k| index.get(k.as_str()).copied())
+                .or_else(|| value.as_u64().and_then(|n| ids.get(n as usize).copied()))
+                .ok_or_else(|| invalid(format!("links[{i}]: unknown node {value}")))
+        };
+        let from = endpoint("source")?;
+        let to = endpoint("target")?;
+        let mut edge = EdgeData::new(from, to);
+        edge.attrs = attributes(members);
+        edge.weight =
+            take_weight(&mut edge.attrs).map_err(|e| invalid(format!("links[{i}]: {e}")))?;
+        data.edges.push(edge);
+    }
+    Ok(data)
+}
+
+/// Writes `data` as node-link JSON in the layout networkx reads back with
+/// `node_link_graph`.
+pub fn write(data: &GraphData) -> String {
+    let keys = node_keys(data);
+    let mut names = HashMap::new();
+    let mut nodes = Vec::with_capacity(data.nodes.len());
+
+    for (node, name) in data.nodes.iter().zip(&keys) {
+        let id = id_value(name);
+        names.insert(node.id, id.clone());
+
+        let mut obj: Map<String, Value> = node
+            .attrs
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::from(v)))
+            .collect();
+        obj.insert("id".to_string(), id);
+        if node.label != *name {
+            obj.insert("label".to_string(), Value::String(node.label.clone()));
+        }
+        if let Some(color) = node.color {
+            obj.insert(
+                "color".to_string(),
+                Value::String(color.to_srgba().to_hex()),
+            );
+        }
+        if let Some(pos) = node.pos {
+            obj.insert("x".to_string(), float(pos.x as f64));
+            obj.insert("y".to_string(), float(pos.y as f64));
+        }
+        if node.pinned {
+            obj.insert("pinned".to_string(), Value::Bool(true));
+        }
+        nodes.push(Value::Object(obj));
+    }
+
+    let mut links = Vec::with_capacity(data.edges.len());
+    for edge in &data.edges {
+        let (Some(source), Some(target)) = (names.get(&edge.from), names.get(&edge.to)) else {
+            continue;
+        };
+        let mut obj: Map<String, Value> = edge
+            .attrs_with_weight()
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::from(v)))
+            .collect();
+        obj.insert("source".to_string(), source.clone());
+        obj.insert("target".to_string(), target.clone());
+        links.push(Value::Object(obj));
+    }
+
+    let mut root = Map::new();
+    root.insert("directed".to_string(), Value::Bool(data.directed));
+    root.insert("multigraph".to_string(), Value::Bool(false));
+    root.insert("graph".to_string(), Value::Object(Map::new()));
+    root.insert("nodes".to_string(), Value::Array(nodes));
+    root.insert("links".to_string(), Value::Array(links));
+    let mut out = serde_json::to_string_pretty(&Value::Object(root)).unwrap_or_default();
+    out.push('\n');
+    out
+}
+
+impl From<&AttrValue> for Value {
+    fn from(value: &AttrValue) -> Self {
+        match value {
+            AttrValue::Bool(b) => Value::Bool(*b),
+            AttrValue::Int(i) => Value::from(*i),
+            AttrValue::Float(f) => float(*f),
+            AttrValue::Str(s) => Value::String(s.clone()),
+            AttrValue::List(items) => Value::Array(items.iter().map(Value::from).collect()),
+            AttrValue::Map(map) => Value::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Converts a JSON value to an attribute; `null` has no attribute form.
+fn attr_value(value: Value) -> Option<AttrValue> {
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(b) => AttrValue::Bool(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => AttrValue::Int(i),
+            None => AttrValue::Float(n.as_f64()?),
+        },
+        Value::String(s) => AttrValue::Str(s),
+        Value::Array(items) => AttrValue::List(items.into_iter().filter_map(attr_value).collect()),
+        Value::Object(map) => AttrValue::Map(attributes(map)),
+    })
+}
+
+fn attributes(members: Map<String, Value>) -> BTreeMap<String, AttrValue> {
+    members
+        .into_iter()
+        .filter_map(|(k, v)| Some((k, attr_value(v)?)))
+        .collect()
+}
+
+/// Removes and returns the position stored as `x`/`y` or `pos: [x, y]`.
+fn take_pos(members: &mut Map<String, Value>) -> Option<Vec2> {
+    if let (Some(x), Some(y)) = (
+        members.get("x").and_then(Value::as_f64),
+        members.get("y").and_then(Value::as_f64),
+    ) {
+        members.remove("x");
+        members.remove("y");
+        return Some(Vec2::new(x as f32, y as f32));
+    }
+    let pos = match members.get("pos") {
+        Some(Value::Array(xy)) if xy.len() == 2 => {
+            Vec2::new(xy[0].as_f64()? as f32, xy[1].as_f64()? as f32)
+        }
+        _ => return None,
+    };
+    members.remove("pos");
+    Some(pos)
+}
+
+/// The name a node id value is looked up under.
+fn key(id: &Value) -> Option<String> {
+    match id {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Writes integer-looking ids as JSON numbers, like networkx does.
+fn id_value(name: &str) -> Value {
+    match name.parse::<u64>() {
+        Ok(n) if n.to_string() == name => Value::from(n),
+        _ => Value::String(name.to_string()),
+    }
+}
+
+fn float(f: f64) -> Value {
+    Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+fn invalid(message: impl Into<String>) -> LoadError {
+    LoadError::Invalid(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::tests::sample;
+
+    #[test]
+    fn round_trip() {
+        let mut data = sample();
+        data.nodes[3].label = "four".to_string();
+        data.nodes[1].attrs.insert(
+            "tags".to_string(),
+            AttrValue::List(vec![AttrValue::Int(1), AttrValue::Str("x".to_string())]),
+        );
+        data.edges[1]
+            .attrs
+            .insert("since".to_string(), AttrValue::Int(2019));
+        assert_eq!(parse(&write(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn syntax_error_is_reported() {
+        let content = "{\n  \"nodes\": [\n    {\"id\": 1}\n    {\"id\": 2}\n  ]\n}\n";
+        match parse(content) {
+            Err(LoadError::Parse(err)) => assert_eq!(err.line, 4),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy::color::{Color, palettes::css};
+use bevy::math::Vec2;
+
+use crate::components::AttrValue;
+use crate::scripts::color_from_hex;
+
+pub mod dot;
+pub mod edge_list;
+pub mod graphml;
+pub mod json;
+
+/// Format-independent description of a graph, produced by the readers and
+/// turned into `GNode`/`GEdge` entities by the loader.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct GraphData {
+    pub directed: bool,
+    pub nodes: Vec<NodeData>,
+    pub edges: Vec<EdgeData>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeData {
+    pub id: usize,
+    pub label: String,
+    /// Saved layout position; nodes without one are scattered randomly.
+    pub pos: Option<Vec2>,
+    pub color: Option<Color>,
+    /// Held in place by the physics simulation.
+    pub pinned: bool,
+    pub attrs: BTreeMap<String, AttrValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeData {
+    pub from: usize,
+    pub to: usize,
+    pub weight: f32,
+    pub attrs: BTreeMap<String, AttrValue>,
+}
+
+impl NodeData {
+    pub fn new(id: usize) -> Self {
+        NodeData {
+            id,
+            label: id.to_string(),
+            pos: None,
+            color: None,
+            pinned: false,
+            attrs: BTreeMap::new(),
+        }
+    }
+}
+
+impl EdgeData {
+    pub fn new(from: usize, to: usize) -> Self {
+        EdgeData {
+            from,
+            to,
+            weight: 1.,
+            attrs: BTreeMap::new(),
+        }
+    }
+
+    /// The attributes to write for this edge, with the weight stored as a
+    /// `weight` attribute unless it is the default of 1.
+    pub fn attrs_with_weight(&self) -> BTreeMap<String, AttrValue> {
+        let mut attrs = self.attrs.clone();
+        if self.weight != 1. {
+            attrs.insert("weight".to_string(), AttrValue::Float(self.weight as f64));
+        }
+        attrs
+    }
+}
+
+/// Removes a numeric `weight` attribute and returns it, defaulting to 1.
+/// A weight that is not a finite number is an error, as it would turn every
+/// position in the simulation into NaN.
+pub(crate) fn take_weight(attrs: &mut BTreeMap<String, AttrValue>) -> Result<f32, String> {
+    match attrs.get("weight").and_then(AttrValue::as_f64) {
+        Some(w) if !(w as f32).is_finite() => Err(format!("invalid weight `{}`", attrs["weight"])),
+        Some(w) => {
+            attrs.remove("weight");
+            Ok(w as f32)
+        }
+        None => Ok(1.),
+    }
+}
+
+/// Removes a boolean `key` attribute and returns whether it was true.
+/// Booleans, numbers and the strings `true`/`false`/`1`/`0` are understood;
+/// any other value is left in place as an ordinary attribute.
+pub(crate) fn take_flag(attrs: &mut BTreeMap<String, AttrValue>, key: &str) -> bool {
+    let flag = match attrs.get(key) {
+        Some(AttrValue::Bool(b)) => *b,
+        Some(AttrValue::Int(i)) => *i != 0,
+        Some(AttrValue::Str(s)) => match s.as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    attrs.remove(key);
+    flag
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(ParseError),
+    /// Well-formed file whose contents don't describe a graph.
+    Invalid(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{e}"),
+            LoadError::Parse(e) => write!(f, "{e}"),
+            LoadError::Invalid(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(e: ParseError) -> Self {
+        LoadError::Parse(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    EdgeList,
+    GraphMl,
+    Dot,
+    Json,
+}
+
+impl Format {
+    /// Picks the format from the file extension, falling back to an edge list.
+    pub fn from_path(path: &Path) -> Format {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("graphml") => Format::GraphMl,
+            Some("dot") | Some("gv") => Format::Dot,
+            Some("json") => Format::Json,
+            _ => Format::EdgeList,
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<GraphData, LoadError> {
+    let content = fs::read_to_string(path)?;
+    let data = match Format::from_path(path) {
+        Format::EdgeList => edge_list::parse(&content)?,
+        Format::GraphMl => graphml::parse(&content)?,
+        Format::Dot => dot::parse(&content)?,
+        Format::Json => json::parse(&content)?,
+    };
+    Ok(data)
+}
+
+pub fn save(path: &Path, data: &GraphData) -> io::Result<()> {
+    let content = match Format::from_path(path) {
+        Format::EdgeList => edge_list::write(data),
+        Format::GraphMl => graphml::write(data),
+        Format::Dot => dot::write(data),
+        Format::Json => json::write(data),
+    };
+    fs::write(path, content)
+}
+
+/// Assigns numeric node ids to the string keys used by a file. Keys that are
+/// plain integers keep their value, the rest are numbered after the largest
+/// of those.
+pub(crate) fn assign_ids(keys: &[&str]) -> Vec<usize> {
+    let mut used = HashSet::new();
+    let numeric: Vec<Option<usize>> = keys
+        .iter()
+        .map(|k| {
+            k.parse::<usize>()
+                .ok()
+                .filter(|n| n.to_string() == *k && used.insert(*n))
+        })
+        .collect();
+
+    let mut next = used.iter().max().map_or(1, |m| m + 1);
+    numeric
+        .into_iter()
+        .map(|n| {
+            n.unwrap_or_else(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect()
+}
+
+/// Returns the names nodes should be written under: their labels when those
+/// are unique, their numeric ids otherwise.
+pub(crate) fn node_keys(data: &GraphData) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let unique = data
+        .nodes
+        .iter()
+        .all(|n| !n.label.is_empty() && seen.insert(n.label.as_str()));
+    data.nodes
+        .iter()
+        .map(|n| {
+            if unique {
+                n.label.clone()
+            } else {
+                n.id.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Parses a `#rgb`/`#rrggbb`/`#rrggbbaa` hex color or a basic color name.
+pub(crate) fn parse_color(s: &str) -> Option<Color> {
+    let s = s.trim();
+    if s.starts_with('#') {
+        return color_from_hex(s);
+    }
+    let named = match s.to_ascii_lowercase().as_str() {
+        "black" => css::BLACK,
+        "white" => css::WHITE,
+        "red" => css::RED,
+        "green" => css::GREEN,
+        "lime" => css::LIME,
+        "blue" => css::BLUE,
+        "yellow" => css::YELLOW,
+        "orange" => css::ORANGE,
+        "purple" => css::PURPLE,
+        "pink" => css::PINK,
+        "brown" => css::BROWN,
+        "cyan" => css::AQUA,
+        "magenta" => css::FUCHSIA,
+        "gray" | "grey" => css::GRAY,
+        _ => return color_from_hex(s),
+    };
+    Some(named.into())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A directed graph using every field the formats store: positions, a
+    /// pin, a color, a weighted edge and a node without edges or position.
+    pub(crate) fn sample() -> GraphData {
+        let node = |id, pos: Option<(f32, f32)>| NodeData {
+            pos: pos.map(|(x, y)| Vec2::new(x, y)),
+            ..NodeData::new(id)
+        };
+        GraphData {
+            directed: true,
+            nodes: vec![
+                NodeData {
+                    pinned: true,
+                    ..node(1, Some((-120.5, 33.)))
+                },
+                NodeData {
+                    color: Some(Color::srgb_u8(200, 30, 40)),
+                    ..node(2, Some((0.25, -7.)))
+                },
+                node(3, Some((1e4, 2.))),
+                node(4, None),
+            ],
+            edges: vec![
+                EdgeData {
+                    weight: 2.5,
+                    ..EdgeData::new(1, 2)
+                },
+                EdgeData::new(3, 1),
+            ],
+        }
+    }
+
+    /// `data` with its node colors dropped, for formats that do not store
+    /// them.
+    pub(crate) fn without_colors(mut data: GraphData) -> GraphData {
+        for node in &mut data.nodes {
+            node.color = None;
+        }
+        data
+    }
+}
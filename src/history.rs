@@ -0,0 +1,595 @@
+use std::collections::HashMap;
+
+use bevy::{color::palettes::css::*, prelude::*};
+use bevy_egui::EguiContexts;
+
+use crate::components::*;
+use crate::formats::{EdgeData, NodeData};
+use crate::loader::{spawn_edge, spawn_node};
+use crate::{delete_edge, delete_node};
+
+/// Ctrl+Z undoes the last edit, Ctrl+Shift+Z (or Ctrl+Y) redoes it.
+pub fn history_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut steps: MessageWriter<HistoryStep>,
+    mut egui_ctx: EguiContexts,
+) -> Result {
+    if egui_ctx.ctx_mut()?.wants_keyboard_input() {
+        return Ok(());
+    }
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if !ctrl {
+        return Ok(());
+    }
+    if keys.just_pressed(KeyCode::KeyZ) {
+        steps.write(if shift {
+            HistoryStep::Redo
+        } else {
+            HistoryStep::Undo
+        });
+    } else if keys.just_pressed(KeyCode::KeyY) {
+        steps.write(HistoryStep::Redo);
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_history(
+    mut steps: MessageReader<HistoryStep>,
+    mut history: ResMut<History>,
+    mut commands: Commands,
+    assets: Res<GraphAssets>,
+    asset_server: Res<AssetServer>,
+    mut graph: ResMut<Graph>,
+    mut colors: ResMut<NodeColors>,
+    mut nodes: Query<(Entity, &GNode, &mut Transform)>,
+    edges: Query<(Entity, &GEdge)>,
+    mut selected: ResMut<Selected>,
+    mut deletion: ResMut<DeletionRequest>,
+    mut edge_state: ResMut<EdgeCreation>,
+    mut drag: ResMut<DragState>,
+) {
+    // One step per frame: spawns and despawns only land once the frame's
+    // commands are applied, and the next step may depend on them.
+    let Some(&step) = steps.read().next() else {
+        return;
+    };
+    let edit = match step {
+        HistoryStep::Undo => history.undo.pop_back().map(|edit| {
+            let inverse = edit.inverse();
+            history.redo.push(edit);
+            inverse
+        }),
+        HistoryStep::Redo => history.redo.pop().inspect(|edit| {
+            history.undo.push_back(edit.clone());
+        }),
+    };
+    let Some(edit) = edit else {
+        return;
+    };
+
+    let mut ents: HashMap<usize, Entity> = nodes.iter().map(|(e, n, _)| (n.id, e)).collect();
+    // the `nth` of the edges joining two nodes
+    let find_edge = |graph: &Graph, from: usize, to: usize, nth: usize| {
+        let (from, to) = (*ents.get(&from)?, *ents.get(&to)?);
+        let ent = *parallel_edges(graph, &edges, from, to).get(nth)?;
+        edges.get(ent).ok().map(|(ent, e)| (ent, e.from, e.to))
+    };
+
+    match edit {
+        Edit::AddNode(node, node_edges) => {
+            let ent = spawn_node(
+                &mut commands,
+                &assets,
+                &asset_server,
+                node.id,
+                node.label.clone(),
+                node.pos.unwrap_or_default(),
+            );
+            if !node.attrs.is_empty() {
+                commands.entity(ent).insert(Attributes(node.attrs.clone()));
+            }
+            if node.pinned {
+                commands.entity(ent).insert(Pinned);
+            }
+            colors
+                .colors
+                .insert(ent, node.color.unwrap_or(Color::from(BLACK)));
+            graph.add_node(ent);
+            graph.curr_id = graph.curr_id.max(node.id);
+            ents.insert(node.id, ent);
+            // recorded in the order of `Graph::edges`, so parallel edges
+            // come back in their old order
+            for edge in &node_edges {
+                add_edge(
+                    edge,
+                    &ents,
+                    &mut commands,
+                    &assets,
+                    &asset_server,
+                    &mut graph,
+                );
+            }
+        }
+        Edit::RemoveNode(node, _) => {
+            if let Some(&ent) = ents.get(&node.id) {
+                delete_node(ent, &mut graph, &mut commands, &edges);
+                colors.colors.remove(&ent);
+                if selected.0 == Some(ent) {
+                    selected.0 = None;
+                }
+                if deletion.node == Some(ent) {
+                    deletion.node = None;
+                }
+                // an edge being drawn from the node, or the node being
+                // dragged, goes with it
+                if edge_state.from == Some(ent) {
+                    if let Some(temp_line) = edge_state.temp_line.take() {
+                        commands.entity(temp_line).despawn();
+                    }
+                    edge_state.from = None;
+                }
+                if drag.dragging == Some(ent) {
+                    drag.dragging = None;
+                }
+            }
+        }
+        Edit::AddEdge(edge, nth) => {
+            let (Some(&from), Some(&to)) = (ents.get(&edge.from), ents.get(&edge.to)) else {
+                return;
+            };
+            let existing = parallel_edges(&graph, &edges, from, to);
+            let added = add_edge(
+                &edge,
+                &ents,
+                &mut commands,
+                &assets,
+                &asset_server,
+                &mut graph,
+            );
+            // an undone deletion goes back to its old place among the
+            // parallel edges
+            if let (Some(ent), Some(next)) = (added, existing.get(nth)) {
+                graph.edges.retain(|e| e != &ent);
+                if let Some(i) = graph.edges.iter().position(|e| e == next) {
+                    graph.edges.insert(i, ent);
+                }
+            }
+        }
+        Edit::RemoveEdge(edge, nth) => {
+            if let Some((ent, _, _)) = find_edge(&graph, edge.from, edge.to, nth) {
+                delete_edge(ent, &mut graph, &mut commands, &edges);
+                if deletion.edge == Some(ent) {
+                    deletion.edge = None;
+                }
+            }
+        }
+        Edit::MoveNode { id, to, .. } => {
+            if let Some(&ent) = ents.get(&id)
+                && let Ok((_, _, mut tf)) = nodes.get_mut(ent)
+            {
+                tf.translation = to.extend(tf.translation.z);
+            }
+        }
+        Edit::SetWeight {
+            from, to, nth, new, ..
+        } => {
+            if let Some((ent, from, to)) = find_edge(&graph, from, to, nth) {
+                commands.entity(ent).insert(GEdge {
+                    from,
+                    to,
+                    weight: new,
+                });
+                graph.set_weight(ent, new);
+            }
+        }
+        Edit::Recolor(changes) => {
+            for (id, _, new) in changes {
+                if let Some(&ent) = ents.get(&id) {
+                    colors.colors.insert(ent, new);
+                }
+            }
+        }
+        Edit::Relabel { id, new, .. } => {
+            if let Some(&ent) = ents.get(&id) {
+                set_label(&mut commands, ent, id, new);
+            }
+        }
+    }
+}
+
+/// Gives a node a new label, on screen as well.
+pub fn set_label(commands: &mut Commands, node: Entity, id: usize, label: String) {
+    commands
+        .entity(node)
+        .insert((Text2d::new(label.clone()), GNode { id, label }));
+}
+
+fn add_edge(
+    edge: &EdgeData,
+    ents: &HashMap<usize, Entity>,
+    commands: &mut Commands,
+    assets: &GraphAssets,
+    asset_server: &AssetServer,
+    graph: &mut Graph,
+) -> Option<Entity> {
+    let (Some(&from), Some(&to)) = (ents.get(&edge.from), ents.get(&edge.to)) else {
+        return None;
+    };
+    let ent = spawn_edge(commands, assets, asset_server, from, to, edge.weight);
+    if !edge.attrs.is_empty() {
+        commands.entity(ent).insert(Attributes(edge.attrs.clone()));
+    }
+    graph.connect(ent, from, to, edge.weight);
+    Some(ent)
+}
+
+/// The edges joining nodes `a` and `b`, either way round unless the graph
+/// is directed, in the order of `Graph::edges`. Edits name one of several
+/// parallel edges by its place in this list.
+pub fn parallel_edges(
+    graph: &Graph,
+    edges: &Query<(Entity, &GEdge)>,
+    a: Entity,
+    b: Entity,
+) -> Vec<Entity> {
+    (graph.edges.iter().copied())
+        .filter(|&ent| {
+            edges.get(ent).is_ok_and(|(_, e)| {
+                (e.from, e.to) == (a, b) || (!graph.directed && (e.from, e.to) == (b, a))
+            })
+        })
+        .collect()
+}
+
+/// Where `edge` comes among the edges joining its two nodes; see
+/// [`parallel_edges`].
+pub fn parallel_index(graph: &Graph, edges: &Query<(Entity, &GEdge)>, edge: Entity) -> usize {
+    let Ok((_, e)) = edges.get(edge) else {
+        return 0;
+    };
+    (parallel_edges(graph, edges, e.from, e.to).iter())
+        .position(|&ent| ent == edge)
+        .unwrap_or(0)
+}
+
+/// Snapshot of an edge, naming its endpoints by node id.
+pub fn edge_record(
+    edge: &GEdge,
+    attrs: Option<&Attributes>,
+    ids: &Query<&GNode>,
+) -> Option<EdgeData> {
+    Some(EdgeData {
+        from: ids.get(edge.from).ok()?.id,
+        to: ids.get(edge.to).ok()?.id,
+        weight: edge.weight,
+        attrs: attrs.map(|a| a.0.clone()).unwrap_or_default(),
+    })
+}
+
+/// Snapshot of a node and every edge touching it, enough to bring them all
+/// back after the node is deleted.
+pub fn node_record(
+    node: Entity,
+    graph: &Graph,
+    ids: &Query<&GNode>,
+    nodes: &Query<(&Transform, Has<Pinned>), With<GNode>>,
+    edges: &Query<(Entity, &GEdge)>,
+    attrs: &Query<&Attributes>,
+    colors: &NodeColors,
+) -> Option<Edit> {
+    let gnode = ids.get(node).ok()?;
+    let (tf, pinned) = nodes.get(node).ok()?;
+    let data = NodeData {
+        id: gnode.id,
+        label: gnode.label.clone(),
+        pos: Some(tf.translation.truncate()),
+        color: colors.colors.get(&node).copied(),
+        pinned,
+        attrs: attrs.get(node).map(|a| a.0.clone()).unwrap_or_default(),
+    };
+    let node_edges = (graph.edges.iter())
+        .filter_map(|&ent| edges.get(ent).ok())
+        .filter(|(_, e)| e.from == node || e.to == node)
+        .filter_map(|(ent, e)| edge_record(e, attrs.get(ent).ok(), ids))
+        .collect();
+    Some(Edit::RemoveNode(data, node_edges))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::AssetPlugin;
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Mesh>()
+            .init_asset::<ColorMaterial>()
+            .init_asset::<Font>()
+            .init_resource::<GraphAssets>()
+            .init_resource::<Graph>()
+            .init_resource::<History>()
+            .init_resource::<DeletionRequest>()
+            .init_resource::<EdgeCreation>()
+            .init_resource::<DragState>()
+            .insert_resource(Selected(None))
+            .insert_resource(NodeColors {
+                colors: HashMap::new(),
+            })
+            .add_message::<HistoryStep>()
+            .add_systems(Update, apply_history);
+        app
+    }
+
+    fn node(world: &mut World, id: usize) -> Entity {
+        world
+            .query::<(Entity, &GNode)>()
+            .iter(world)
+            .find(|(_, n)| n.id == id)
+            .map(|(e, _)| e)
+            .unwrap()
+    }
+
+    /// Adds a node the way clicking in edit mode does.
+    fn add_node(app: &mut App, id: usize) {
+        let system = move |mut commands: Commands,
+                           assets: Res<GraphAssets>,
+                           asset_server: Res<AssetServer>,
+                           mut graph: ResMut<Graph>,
+                           mut colors: ResMut<NodeColors>,
+                           mut history: ResMut<History>| {
+            let pos = Vec2::new(id as f32 * 100., 0.);
+            let ent = spawn_node(
+                &mut commands,
+                &assets,
+                &asset_server,
+                id,
+                id.to_string(),
+                pos,
+            );
+            graph.add_node(ent);
+            graph.curr_id = id;
+            colors.colors.insert(ent, Color::BLACK);
+            history.push(Edit::AddNode(
+                NodeData {
+                    pos: Some(pos),
+                    color: Some(Color::BLACK),
+                    ..NodeData::new(id)
+                },
+                Vec::new(),
+            ));
+        };
+        app.world_mut().run_system_once(system).unwrap();
+    }
+
+    fn add_edge(app: &mut App, from: usize, to: usize, weight: f32) {
+        let (a, b) = (node(app.world_mut(), from), node(app.world_mut(), to));
+        let system = move |mut commands: Commands,
+                           assets: Res<GraphAssets>,
+                           asset_server: Res<AssetServer>,
+                           mut graph: ResMut<Graph>,
+                           mut history: ResMut<History>| {
+            let nth = graph.edge_count(a, b);
+            let ent = spawn_edge(&mut commands, &assets, &asset_server, a, b, weight);
+            graph.connect(ent, a, b, weight);
+            let edge = EdgeData {
+                weight,
+                ..EdgeData::new(from, to)
+            };
+            history.push(Edit::AddEdge(edge, nth));
+        };
+        app.world_mut().run_system_once(system).unwrap();
+    }
+
+    /// Changes the weight of the `nth` edge between nodes 1 and 2 the way
+    /// the Edge popup does.
+    fn set_weight(app: &mut App, nth: usize, weight: f32) {
+        let (a, b) = (node(app.world_mut(), 1), node(app.world_mut(), 2));
+        let system = move |mut commands: Commands,
+                           mut graph: ResMut<Graph>,
+                           mut history: ResMut<History>,
+                           edges: Query<(Entity, &GEdge)>| {
+            let ent = parallel_edges(&graph, &edges, a, b)[nth];
+            let (_, edge) = edges.get(ent).unwrap();
+            commands.entity(ent).insert(GEdge { weight, ..*edge });
+            graph.set_weight(ent, weight);
+            history.push_weight(1, 2, nth, edge.weight, weight);
+        };
+        app.world_mut().run_system_once(system).unwrap();
+    }
+
+    /// Deletes the `nth` edge between nodes 1 and 2 the way the "Delete
+    /// Edge?" popup does.
+    fn remove_edge(app: &mut App, nth: usize) {
+        let (a, b) = (node(app.world_mut(), 1), node(app.world_mut(), 2));
+        let system = move |mut commands: Commands,
+                           mut graph: ResMut<Graph>,
+                           mut history: ResMut<History>,
+                           ids: Query<&GNode>,
+                           edges: Query<(Entity, &GEdge)>| {
+            let ent = parallel_edges(&graph, &edges, a, b)[nth];
+            let record = edge_record(edges.get(ent).unwrap().1, None, &ids).unwrap();
+            history.push(Edit::RemoveEdge(record, nth));
+            delete_edge(ent, &mut graph, &mut commands, &edges);
+        };
+        app.world_mut().run_system_once(system).unwrap();
+    }
+
+    /// Weights of the edges in the order of `Graph::edges`.
+    fn weights(app: &mut App) -> Vec<f32> {
+        let world = app.world_mut();
+        let edges: Vec<Entity> = world.resource::<Graph>().edges.clone();
+        (edges.iter())
+            .map(|&e| world.get::<GEdge>(e).unwrap().weight)
+            .collect()
+    }
+
+    /// Deletes a node the way the "Delete Node?" popup does.
+    fn delete(app: &mut App, id: usize) {
+        let ent = node(app.world_mut(), id);
+        let system = move |mut commands: Commands,
+                           mut graph: ResMut<Graph>,
+                           mut history: ResMut<History>,
+                           ids: Query<&GNode>,
+                           nodes: Query<(&Transform, Has<Pinned>), With<GNode>>,
+                           edges: Query<(Entity, &GEdge)>,
+                           attrs: Query<&Attributes>,
+                           colors: Res<NodeColors>| {
+            let edit = node_record(ent, &graph, &ids, &nodes, &edges, &attrs, &colors).unwrap();
+            history.push(edit);
+            delete_node(ent, &mut graph, &mut commands, &edges);
+        };
+        app.world_mut().run_system_once(system).unwrap();
+        app.world_mut()
+            .resource_mut::<NodeColors>()
+            .colors
+            .remove(&ent);
+    }
+
+    fn step(app: &mut App, step: HistoryStep) {
+        app.world_mut().write_message(step);
+        app.update();
+    }
+
+    /// Steps through the history while an edge is being drawn from node 2
+    /// and node 2 is being dragged, if there is a node 2. Checks that both
+    /// are dropped if and only if the step removes the node.
+    fn step_while_busy(app: &mut App, history_step: HistoryStep) {
+        let world = app.world_mut();
+        let busy = (world.query::<(Entity, &GNode)>().iter(world))
+            .find(|(_, n)| n.id == 2)
+            .map(|(e, _)| e);
+        let line = busy.map(|_| world.spawn_empty().id());
+        if let Some(ent) = busy {
+            *world.resource_mut::<EdgeCreation>() = EdgeCreation {
+                from: Some(ent),
+                temp_line: line,
+            };
+            world.resource_mut::<DragState>().dragging = Some(ent);
+        }
+        step(app, history_step);
+
+        let world = app.world();
+        let kept = busy.filter(|&e| world.get_entity(e).is_ok());
+        let edge_state = world.resource::<EdgeCreation>();
+        assert_eq!(edge_state.from, kept);
+        assert_eq!(edge_state.temp_line, line.filter(|_| kept.is_some()));
+        if let Some(line) = line {
+            assert_eq!(world.get_entity(line).is_ok(), kept.is_some());
+        }
+        assert_eq!(world.resource::<DragState>().dragging, kept);
+    }
+
+    /// Checks that the world holds exactly `nodes` and `edges`, and that
+    /// `Graph` and `NodeColors` agree with the entities.
+    fn check(app: &mut App, nodes: &[usize], edges: &[(usize, usize, f32)]) {
+        let world = app.world_mut();
+        let ids: HashMap<Entity, usize> = world
+            .query::<(Entity, &GNode)>()
+            .iter(world)
+            .map(|(e, n)| (e, n.id))
+            .collect();
+        let gedges: Vec<(Entity, Entity, Entity, f32)> = world
+            .query::<(Entity, &GEdge)>()
+            .iter(world)
+            .map(|(e, edge)| (e, edge.from, edge.to, edge.weight))
+            .collect();
+
+        let mut node_ids: Vec<usize> = ids.values().copied().collect();
+        node_ids.sort();
+        assert_eq!(node_ids, nodes);
+        let mut edge_ids: Vec<(usize, usize, f32)> = gedges
+            .iter()
+            .map(|&(_, from, to, w)| (ids[&from], ids[&to], w))
+            .collect();
+        edge_ids.sort_by_key(|&(from, to, _)| (from, to));
+        assert_eq!(edge_ids, edges);
+
+        let graph = world.resource::<Graph>();
+        let mut expected: HashMap<Entity, Vec<Entity>> =
+            ids.keys().map(|&e| (e, Vec::new())).collect();
+        for &(_, from, to, _) in &gedges {
+            expected.get_mut(&from).unwrap().push(to);
+            expected.get_mut(&to).unwrap().push(from);
+        }
+        for list in [&graph.adj, &graph.in_adj] {
+            let mut actual = list.clone();
+            for neighbours in actual.values_mut().chain(expected.values_mut()) {
+                neighbours.sort();
+            }
+            assert_eq!(actual, expected);
+        }
+        let mut graph_edges = graph.edges.clone();
+        graph_edges.sort();
+        let mut edge_ents: Vec<Entity> = gedges.iter().map(|e| e.0).collect();
+        edge_ents.sort();
+        assert_eq!(graph_edges, edge_ents);
+        let weights: HashMap<Entity, f32> = gedges.iter().map(|e| (e.0, e.3)).collect();
+        assert_eq!(graph.weights, weights);
+
+        let colors = &world.resource::<NodeColors>().colors;
+        assert!(colors.len() == ids.len() && ids.keys().all(|e| colors.contains_key(e)));
+    }
+
+    type State = (&'static [usize], &'static [(usize, usize, f32)]);
+
+    #[test]
+    fn undo_and_redo_keep_graph_and_entities_in_step() {
+        let mut app = app();
+        add_node(&mut app, 1);
+        app.world_mut().resource_mut::<History>().undo.clear();
+
+        add_node(&mut app, 2);
+        add_edge(&mut app, 1, 2, 2.5);
+        delete(&mut app, 2);
+
+        let states: [State; 4] = [
+            (&[1], &[]),
+            (&[1, 2], &[]),
+            (&[1, 2], &[(1, 2, 2.5)]),
+            (&[1], &[]),
+        ];
+        check(&mut app, states[3].0, states[3].1);
+        for (nodes, edges) in states[..3].iter().rev() {
+            step_while_busy(&mut app, HistoryStep::Undo);
+            check(&mut app, nodes, edges);
+        }
+        assert!(app.world().resource::<History>().undo.is_empty());
+        for (nodes, edges) in &states[1..] {
+            step_while_busy(&mut app, HistoryStep::Redo);
+            check(&mut app, nodes, edges);
+        }
+        assert!(app.world().resource::<History>().redo.is_empty());
+    }
+
+    #[test]
+    fn parallel_edges_are_told_apart() {
+        let mut app = app();
+        add_node(&mut app, 1);
+        add_node(&mut app, 2);
+        add_edge(&mut app, 1, 2, 1.);
+        add_edge(&mut app, 1, 2, 5.);
+        app.world_mut().resource_mut::<History>().undo.clear();
+
+        set_weight(&mut app, 1, 7.);
+        app.update();
+        remove_edge(&mut app, 0);
+        app.update();
+        let states: [&[f32]; 3] = [&[1., 5.], &[1., 7.], &[7.]];
+        assert_eq!(weights(&mut app), states[2]);
+        for expected in states[..2].iter().rev() {
+            step(&mut app, HistoryStep::Undo);
+            assert_eq!(weights(&mut app), *expected);
+        }
+        for expected in &states[1..] {
+            step(&mut app, HistoryStep::Redo);
+            assert_eq!(weights(&mut app), *expected);
+        }
+        let edges: Vec<_> = states[2].iter().map(|&w| (1, 2, w)).collect();
+        check(&mut app, &[1, 2], &edges);
+    }
+}
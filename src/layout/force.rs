@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+
+use super::{EDGE_LENGTH, LayoutGraph};
+use crate::physics::quadtree::QuadTree;
+
+const ITERATIONS: usize = 300;
+
+/// Fruchterman–Reingold: nodes repel with `k² / d` and edges pull with
+/// `d² / k`, where `k` is the preferred edge length, while a temperature
+/// that cools linearly caps how far a node may move per iteration.
+/// Repulsion is summed with the Barnes–Hut quadtree, so each iteration is
+/// O(n log n).
+pub fn fruchterman_reingold(graph: &LayoutGraph) -> Vec<Vec2> {
+    let k = EDGE_LENGTH;
+    let mut pos = graph.spread_positions();
+    let start_temp = k * (graph.len() as f32).sqrt() / 4.;
+
+    for iter in 0..ITERATIONS {
+        let temp = start_temp * (1. - iter as f32 / ITERATIONS as f32);
+        let tree = QuadTree::new(pos.iter().map(|&p| (p, 1.)));
+        let mut disp: Vec<Vec2> = pos
+            .iter()
+            .map(|&p| tree.repulsion(p, 1.) * (k * k))
+            .collect();
+        for &(u, v, w) in &graph.edges {
+            let delta = pos[u] - pos[v];
+            let pull = delta * (delta.length() / k) * w.max(0.);
+            disp[u] -= pull;
+            disp[v] += pull;
+        }
+        for (i, d) in disp.into_iter().enumerate() {
+            if graph.fixed[i] {
+                continue;
+            }
+            let len = d.length();
+            if len > 0. {
+                pos[i] += d / len * len.min(temp);
+            }
+        }
+    }
+    pos
+}
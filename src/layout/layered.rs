@@ -0,0 +1,349 @@
+use bevy::prelude::*;
+
+use super::{EDGE_LENGTH, LayoutGraph, LayoutResult, NODE_SPACING, Orientation};
+
+/// Barycenter sweeps in the crossing minimization phase.
+const ORDER_SWEEPS: usize = 24;
+/// Passes of the coordinate assignment phase.
+const POSITION_PASSES: usize = 8;
+
+/// Sugiyama-style layered drawing. Edges point from lower to higher layers
+/// once a few are reversed to break cycles; edges that span several layers
+/// get a bend point in every layer they cross, so that they run between the
+/// nodes instead of through them.
+pub fn layered(graph: &LayoutGraph, orientation: Orientation) -> LayoutResult {
+    let n = graph.len();
+    let edges = acyclic_edges(graph);
+    let layer = assign_layers(n, &edges);
+
+    // --- Split long edges into chains through dummy vertices, one per layer
+    let mut layer_of = layer.clone();
+    let mut chains = Vec::with_capacity(edges.len()); // vertex chain per edge
+    for &(u, v, reversed) in &edges {
+        let mut chain = vec![u];
+        for l in layer[u] + 1..layer[v] {
+            chain.push(layer_of.len());
+            layer_of.push(l);
+        }
+        chain.push(v);
+        if reversed {
+            chain.reverse();
+        }
+        chains.push(chain);
+    }
+    let vertices = layer_of.len();
+    let mut down = vec![Vec::new(); vertices]; // neighbours in the layer below
+    let mut up = vec![Vec::new(); vertices];
+    for chain in &chains {
+        for pair in chain.windows(2) {
+            if pair[0] == pair[1] {
+                continue; // self-loop: not a neighbour in another layer
+            }
+            let (a, b) = if layer_of[pair[0]] < layer_of[pair[1]] {
+                (pair[0], pair[1])
+            } else {
+                (pair[1], pair[0])
+            };
+            down[a].push(b);
+            up[b].push(a);
+        }
+    }
+
+    let depth = layer_of.iter().max().map_or(0, |&l| l + 1);
+    let mut layers = vec![Vec::new(); depth];
+    for (v, &l) in layer_of.iter().enumerate() {
+        layers[l].push(v);
+    }
+
+    order_layers(&mut layers, &up, &down);
+    let x = assign_coordinates(&layers, &up, &down, vertices);
+
+    // --- Turn (layer, x) into world positions around the old centroid
+    let center = graph.centroid();
+    let width = x.iter().cloned().fold(0., f32::max);
+    let height = (depth.max(1) - 1) as f32 * EDGE_LENGTH;
+    let place = |v: usize| {
+        let across = x[v] - width / 2.;
+        let along = layer_of[v] as f32 * EDGE_LENGTH - height / 2.;
+        center
+            + match orientation {
+                Orientation::TopToBottom => Vec2::new(across, -along),
+                Orientation::LeftToRight => Vec2::new(along, -across),
+            }
+    };
+
+    let positions = (0..n).map(place).collect();
+    let routes = chains
+        .iter()
+        .map(|chain| {
+            chain[1..chain.len() - 1]
+                .iter()
+                .map(|&v| place(v))
+                .collect()
+        })
+        .collect();
+    LayoutResult {
+        positions,
+        routes,
+        dimmed: Vec::new(),
+    }
+}
+
+/// The graph's edges as `(from, to, reversed)`, with the edges that close a
+/// cycle in a depth-first search turned around so that no cycles are left.
+/// Self-loops are left as they are; the layering ignores them.
+fn acyclic_edges(graph: &LayoutGraph) -> Vec<(usize, usize, bool)> {
+    let n = graph.len();
+    let mut out_edges = vec![Vec::new(); n];
+    for (e, &(u, v, _)) in graph.edges.iter().enumerate() {
+        out_edges[u].push((v, e));
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        Active,
+        Done,
+    }
+    let mut mark = vec![Mark::New; n];
+    let mut reversed = vec![false; graph.edges.len()];
+    for root in 0..n {
+        if mark[root] != Mark::New {
+            continue;
+        }
+        // iterative DFS: (vertex, index of the next out-edge to look at)
+        let mut stack = vec![(root, 0)];
+        mark[root] = Mark::Active;
+        while let Some((u, next)) = stack.last_mut() {
+            let u = *u;
+            if let Some(&(v, e)) = out_edges[u].get(*next) {
+                *next += 1;
+                match mark[v] {
+                    Mark::New => {
+                        mark[v] = Mark::Active;
+                        stack.push((v, 0));
+                    }
+                    Mark::Active => reversed[e] = u != v,
+                    Mark::Done => {}
+                }
+            } else {
+                mark[u] = Mark::Done;
+                stack.pop();
+            }
+        }
+    }
+
+    graph
+        .edges
+        .iter()
+        .zip(reversed)
+        .map(|(&(u, v, _), rev)| if rev { (v, u, true) } else { (u, v, false) })
+        .collect()
+}
+
+/// Longest-path layering: sources go in layer 0 and every other vertex one
+/// layer below its lowest predecessor.
+fn assign_layers(n: usize, edges: &[(usize, usize, bool)]) -> Vec<usize> {
+    let mut succ = vec![Vec::new(); n];
+    let mut indegree = vec![0; n];
+    for &(u, v, _) in edges {
+        if u != v {
+            succ[u].push(v);
+            indegree[v] += 1;
+        }
+    }
+    let mut layer = vec![0; n];
+    let mut ready: Vec<usize> = (0..n).filter(|&v| indegree[v] == 0).collect();
+    while let Some(u) = ready.pop() {
+        for &v in &succ[u] {
+            layer[v] = layer[v].max(layer[u] + 1);
+            indegree[v] -= 1;
+            if indegree[v] == 0 {
+                ready.push(v);
+            }
+        }
+    }
+    layer
+}
+
+/// Reorders each layer to reduce edge crossings with the barycenter
+/// heuristic, sweeping down and up alternately and keeping the best order.
+fn order_layers(layers: &mut [Vec<usize>], up: &[Vec<usize>], down: &[Vec<usize>]) {
+    let mut pos = vec![0.; up.len()]; // index of each vertex within its layer
+    for layer in layers.iter() {
+        for (i, &v) in layer.iter().enumerate() {
+            pos[v] = i as f32;
+        }
+    }
+    let mut best = layers.to_vec();
+    let mut best_crossings = crossings(layers, down, &pos);
+
+    for sweep in 0..ORDER_SWEEPS {
+        let downward = sweep % 2 == 0;
+        let order: Vec<usize> = if downward {
+            (1..layers.len()).collect()
+        } else {
+            (0..layers.len().saturating_sub(1)).rev().collect()
+        };
+        for l in order {
+            let fixed = if downward { up } else { down };
+            let mut keyed: Vec<(f32, usize)> = layers[l]
+                .iter()
+                .map(|&v| {
+                    let ns = &fixed[v];
+                    let key = if ns.is_empty() {
+                        pos[v] // no neighbours on that side: stay put
+                    } else {
+                        ns.iter().map(|&u| pos[u]).sum::<f32>() / ns.len() as f32
+                    };
+                    (key, v)
+                })
+                .collect();
+            keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+            layers[l] = keyed.into_iter().map(|(_, v)| v).collect();
+            for (i, &v) in layers[l].iter().enumerate() {
+                pos[v] = i as f32;
+            }
+        }
+        let c = crossings(layers, down, &pos);
+        if c < best_crossings {
+            best_crossings = c;
+            best = layers.to_vec();
+        }
+    }
+    layers.clone_from_slice(&best);
+}
+
+/// Number of pairs of edges that cross between consecutive layers.
+fn crossings(layers: &[Vec<usize>], down: &[Vec<usize>], pos: &[f32]) -> usize {
+    let mut total = 0;
+    for layer in layers {
+        // edges to the next layer as (upper position, lower position), sorted
+        // by the upper end; crossings are then inversions of the lower ends
+        let mut ends: Vec<(f32, f32)> = layer
+            .iter()
+            .flat_map(|&u| down[u].iter().map(move |&v| (pos[u], pos[v])))
+            .collect();
+        ends.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        let mut lower: Vec<f32> = ends.into_iter().map(|(_, l)| l).collect();
+        total += inversions(&mut lower);
+    }
+    total
+}
+
+/// Counts pairs out of order, sorting `v` along the way (merge sort).
+fn inversions(v: &mut [f32]) -> usize {
+    if v.len() < 2 {
+        return 0;
+    }
+    let mid = v.len() / 2;
+    let mut count = inversions(&mut v[..mid]) + inversions(&mut v[mid..]);
+    let mut merged = Vec::with_capacity(v.len());
+    let (mut i, mut j) = (0, mid);
+    while i < mid && j < v.len() {
+        if v[j] < v[i] {
+            count += mid - i;
+            merged.push(v[j]);
+            j += 1;
+        } else {
+            merged.push(v[i]);
+            i += 1;
+        }
+    }
+    merged.extend_from_slice(&v[i..mid]);
+    merged.extend_from_slice(&v[j..]);
+    v.copy_from_slice(&merged);
+    count
+}
+
+/// Positions within each layer: every vertex is pulled towards the mean of
+/// its neighbours in the adjacent layers, subject to keeping the layer's
+/// order and `NODE_SPACING` between neighbours.
+fn assign_coordinates(
+    layers: &[Vec<usize>],
+    up: &[Vec<usize>],
+    down: &[Vec<usize>],
+    vertices: usize,
+) -> Vec<f32> {
+    let mut x = vec![0.; vertices];
+    for layer in layers {
+        for (i, &v) in layer.iter().enumerate() {
+            x[v] = i as f32 * NODE_SPACING;
+        }
+    }
+
+    for pass in 0..POSITION_PASSES {
+        let neighbours = if pass % 2 == 0 { up } else { down };
+        for layer in layers {
+            let desired: Vec<f32> = layer
+                .iter()
+                .map(|&v| {
+                    let ns = &neighbours[v];
+                    if ns.is_empty() {
+                        x[v]
+                    } else {
+                        ns.iter().map(|&u| x[u]).sum::<f32>() / ns.len() as f32
+                    }
+                })
+                .collect();
+            // closest placements from the left and from the right that keep
+            // the spacing; their average keeps it too
+            let mut left = desired.clone();
+            for i in 1..left.len() {
+                left[i] = left[i].max(left[i - 1] + NODE_SPACING);
+            }
+            let mut right = desired;
+            for i in (0..right.len().saturating_sub(1)).rev() {
+                right[i] = right[i].min(right[i + 1] - NODE_SPACING);
+            }
+            for (i, &v) in layer.iter().enumerate() {
+                x[v] = (left[i] + right[i]) / 2.;
+            }
+        }
+    }
+
+    // shift so the leftmost vertex sits at 0
+    let min = x.iter().cloned().fold(f32::INFINITY, f32::min);
+    if min.is_finite() {
+        for v in &mut x {
+            *v -= min;
+        }
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: Vec<(usize, usize, f32)>) -> LayoutGraph {
+        LayoutGraph {
+            positions: vec![Vec2::ZERO; 7],
+            fixed: vec![false; 7],
+            edges,
+            directed: true,
+            root: None,
+            seed: 0,
+        }
+    }
+
+    #[test]
+    fn self_loops_do_not_move_nodes() {
+        let edges = vec![
+            (0, 1, 1.),
+            (0, 2, 1.),
+            (0, 3, 1.),
+            (3, 4, 1.),
+            (1, 5, 1.),
+            (2, 6, 1.),
+            (1, 6, 1.),
+        ];
+        let mut looped = edges.clone();
+        looped.insert(1, (2, 2, 1.));
+        looped.push((5, 5, 1.));
+        let plain = layered(&graph(edges), Orientation::TopToBottom);
+        let out = layered(&graph(looped), Orientation::TopToBottom);
+        assert_eq!(out.positions, plain.positions);
+        assert!(out.routes[1].is_empty());
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, futures::check_ready};
+
+use crate::components::*;
+
+mod force;
+mod layered;
+mod simple;
+mod stress;
+mod tree;
+
+/// Preferred distance between neighbouring nodes, in world units.
+pub const EDGE_LENGTH: f32 = 200.;
+
+/// Minimum distance between side-by-side nodes in layered and tree layouts.
+const NODE_SPACING: f32 = EDGE_LENGTH * 0.75;
+
+/// Largest graph Kamada-Kawai lays out; its n × n distance matrix takes
+/// 8 bytes per node pair, so larger graphs get Fruchterman-Reingold instead.
+pub const STRESS_MAX_NODES: usize = 2000;
+
+/// How long nodes take to glide from their old positions to the new layout.
+const ANIMATION_SECS: f32 = 1.;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LayoutKind {
+    #[default]
+    FruchtermanReingold,
+    KamadaKawai,
+    Circular,
+    Grid,
+    Random,
+    Layered(Orientation),
+    Tree,
+    RadialTree,
+}
+
+/// Which way the layers of a layered drawing follow each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    TopToBottom,
+    LeftToRight,
+}
+
+impl LayoutKind {
+    pub const ALL: [LayoutKind; 9] = [
+        LayoutKind::FruchtermanReingold,
+        LayoutKind::KamadaKawai,
+        LayoutKind::Circular,
+        LayoutKind::Grid,
+        LayoutKind::Random,
+        LayoutKind::Layered(Orientation::TopToBottom),
+        LayoutKind::Layered(Orientation::LeftToRight),
+        LayoutKind::Tree,
+        LayoutKind::RadialTree,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LayoutKind::FruchtermanReingold => "Fruchterman-Reingold",
+            LayoutKind::KamadaKawai => "Kamada-Kawai (stress)",
+            LayoutKind::Circular => "Circular",
+            LayoutKind::Grid => "Grid",
+            LayoutKind::Random => "Random",
+            LayoutKind::Layered(Orientation::TopToBottom) => "Layered (top to bottom)",
+            LayoutKind::Layered(Orientation::LeftToRight) => "Layered (left to right)",
+            LayoutKind::Tree => "Tree",
+            LayoutKind::RadialTree => "Radial tree",
+        }
+    }
+}
+
+/// Snapshot of the graph handed to a layout algorithm: node `i` is the
+/// `i`-th node in order of `GNode::id`, and edges refer to those indices.
+pub struct LayoutGraph {
+    pub positions: Vec<Vec2>,
+    pub fixed: Vec<bool>, // pinned nodes, which keep their positions
+    pub edges: Vec<(usize, usize, f32)>, // (from, to, weight)
+    pub directed: bool,
+    pub root: Option<usize>, // the selected node, for tree layouts
+    pub seed: u64,
+}
+
+impl LayoutGraph {
+    fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Mean of the current positions, which the new layout is centred on so
+    /// the graph does not jump away from where the camera is looking.
+    fn centroid(&self) -> Vec2 {
+        if self.positions.is_empty() {
+            return Vec2::ZERO;
+        }
+        self.positions.iter().sum::<Vec2>() / self.len() as f32
+    }
+
+    /// Indices of the nodes a layout may move.
+    fn free(&self) -> Vec<usize> {
+        (0..self.len()).filter(|&i| !self.fixed[i]).collect()
+    }
+
+    /// Undirected adjacency lists.
+    fn neighbours(&self) -> Vec<Vec<usize>> {
+        let mut adj = vec![Vec::new(); self.len()];
+        for &(u, v, _) in &self.edges {
+            if u != v {
+                adj[u].push(v);
+                adj[v].push(u);
+            }
+        }
+        adj
+    }
+
+    /// Current positions, with nodes that share a position nudged apart so
+    /// that iterative layouts can tell them apart.
+    fn spread_positions(&self) -> Vec<Vec2> {
+        let mut seen = HashMap::new();
+        self.positions
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let copies = seen.entry((p.x.to_bits(), p.y.to_bits())).or_insert(0);
+                *copies += 1;
+                if *copies == 1 || self.fixed[i] {
+                    p
+                } else {
+                    p + Vec2::from_angle(*copies as f32 * 2.4) * (*copies as f32)
+                }
+            })
+            .collect()
+    }
+}
+
+/// What a layout produces: the new position of every node and, per edge,
+/// the bend points it should be drawn through (empty for a straight edge)
+/// and whether it should be drawn dimmed. Either list may be empty when
+/// the layout has nothing to say about edges.
+pub struct LayoutResult {
+    pub positions: Vec<Vec2>,
+    pub routes: Vec<Vec<Vec2>>,
+    pub dimmed: Vec<bool>,
+}
+
+impl From<Vec<Vec2>> for LayoutResult {
+    fn from(positions: Vec<Vec2>) -> Self {
+        LayoutResult {
+            positions,
+            routes: Vec::new(),
+            dimmed: Vec::new(),
+        }
+    }
+}
+
+/// Runs `kind` over `graph`.
+pub fn compute(kind: LayoutKind, graph: &LayoutGraph) -> LayoutResult {
+    let mut out: LayoutResult = match kind {
+        LayoutKind::FruchtermanReingold => force::fruchterman_reingold(graph).into(),
+        LayoutKind::KamadaKawai if graph.len() <= STRESS_MAX_NODES => {
+            stress::stress_majorization(graph).into()
+        }
+        LayoutKind::KamadaKawai => force::fruchterman_reingold(graph).into(),
+        LayoutKind::Circular => simple::circular(graph).into(),
+        LayoutKind::Grid => simple::grid(graph).into(),
+        LayoutKind::Random => simple::random(graph).into(),
+        LayoutKind::Layered(orientation) => layered::layered(graph, orientation),
+        LayoutKind::Tree => tree::tree(graph),
+        LayoutKind::RadialTree => tree::radial_tree(graph),
+    };
+    for (i, p) in out.positions.iter_mut().enumerate() {
+        if graph.fixed[i] {
+            *p = graph.positions[i];
+        }
+    }
+    // bends were planned for where the layout wanted the pinned nodes
+    for (route, &(u, v, _)) in out.routes.iter_mut().zip(&graph.edges) {
+        if graph.fixed[u] || graph.fixed[v] {
+            route.clear();
+        }
+    }
+    out
+}
+
+/// Starts computing the requested layout in the background. The live
+/// physics simulation is switched off so it does not undo the result; the
+/// layout UI says so until it is switched back on.
+pub fn start_layout(
+    mut requests: MessageReader<RunLayout>,
+    mut state: ResMut<LayoutState>,
+    mut config: ResMut<Config>,
+    nodes: Query<(Entity, &GNode, &Transform, Has<Pinned>)>,
+    edges: Query<(Entity, &GEdge)>,
+    graph: Res<Graph>,
+    selected: Res<Selected>,
+) {
+    let Some(&RunLayout(kind)) = requests.read().last() else {
+        return;
+    };
+
+    let mut sorted: Vec<_> = nodes.iter().collect();
+    sorted.sort_by_key(|(_, node, _, _)| node.id);
+    let index: HashMap<Entity, usize> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, (ent, _, _, _))| (*ent, i))
+        .collect();
+    let mut input = LayoutGraph {
+        positions: sorted
+            .iter()
+            .map(|(_, _, tf, _)| tf.translation.truncate())
+            .collect(),
+        fixed: sorted.iter().map(|(_, _, _, pinned)| *pinned).collect(),
+        edges: Vec::new(),
+        directed: graph.directed,
+        root: selected.0.and_then(|ent| index.get(&ent).copied()),
+        seed: config.seed,
+    };
+    let mut edge_ents = Vec::new();
+    for (ent, e) in edges.iter() {
+        if let (Some(&u), Some(&v)) = (index.get(&e.from), index.get(&e.to)) {
+            input.edges.push((u, v, e.weight));
+            edge_ents.push(ent);
+        }
+    }
+
+    state.task = Some(LayoutTask {
+        nodes: sorted.iter().map(|(ent, _, _, _)| *ent).collect(),
+        edges: edge_ents,
+        task: AsyncComputeTaskPool::get().spawn(async move { compute(kind, &input) }),
+    });
+    state.moves.clear();
+    state.stopped_physics |= config.enabled;
+    config.enabled = false;
+}
+
+/// Picks up a finished layout and moves the nodes towards it. Edge bends
+/// and dimming are set right away; bends take effect once the nodes
+/// arrive.
+pub fn animate_layout(
+    mut commands: Commands,
+    mut state: ResMut<LayoutState>,
+    mut nodes: Query<&mut Transform, With<GNode>>,
+    edges: Query<&GEdge>,
+    time: Res<Time>,
+) {
+    if let Some(layout) = &mut state.task
+        && let Some(result) = check_ready(&mut layout.task)
+    {
+        let targets: HashMap<Entity, Vec2> =
+            layout.nodes.iter().copied().zip(result.positions).collect();
+        let mut routes = result.routes.into_iter();
+        let mut dimmed = result.dimmed.into_iter();
+        for &ent in &layout.edges {
+            let bends = routes.next().unwrap_or_default();
+            let Ok(edge) = edges.get(ent) else {
+                continue;
+            };
+            if dimmed.next().unwrap_or(false) {
+                commands.entity(ent).try_insert(Dimmed);
+            } else {
+                commands.entity(ent).try_remove::<Dimmed>();
+            }
+            if bends.is_empty() {
+                commands.entity(ent).try_remove::<EdgeRoute>();
+            } else if let (Some(&from), Some(&to)) =
+                (targets.get(&edge.from), targets.get(&edge.to))
+            {
+                commands
+                    .entity(ent)
+                    .try_insert(EdgeRoute { from, to, bends });
+            }
+        }
+        let moves = layout
+            .nodes
+            .iter()
+            .filter_map(|ent| {
+                let from = nodes.get(*ent).ok()?.translation.truncate();
+                Some((*ent, from, targets[ent]))
+            })
+            .collect();
+        state.task = None;
+        state.moves = moves;
+        state.elapsed = 0.;
+    }
+    if state.moves.is_empty() {
+        return;
+    }
+
+    state.elapsed += time.delta_secs();
+    let t = (state.elapsed / ANIMATION_SECS).min(1.);
+    let eased = t * t * (3. - 2. * t);
+    for &(ent, from, to) in &state.moves {
+        if let Ok(mut tf) = nodes.get_mut(ent) {
+            tf.translation = from.lerp(to, eased).extend(tf.translation.z);
+        }
+    }
+    if t >= 1. {
+        state.moves.clear();
+    }
+}
+
+/// Evenly spaced points on a circle of `radius` around `center`, starting
+/// at the top and going clockwise.
+fn ring(count: usize, center: Vec2, radius: f32) -> impl Iterator<Item = Vec2> {
+    (0..count).map(move |i| {
+        let angle = TAU / 4. - TAU * i as f32 / count as f32;
+        center + Vec2::from_angle(angle) * radius
+    })
+}
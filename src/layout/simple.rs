@@ -0,0 +1,48 @@
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use super::{EDGE_LENGTH, LayoutGraph, ring};
+
+/// Places the movable nodes on a circle, in id order.
+pub fn circular(graph: &LayoutGraph) -> Vec<Vec2> {
+    let mut out = graph.positions.clone();
+    let free = graph.free();
+    // neighbours on the circle are about EDGE_LENGTH apart
+    let radius = (EDGE_LENGTH * free.len() as f32 / std::f32::consts::TAU).max(EDGE_LENGTH);
+    for (&i, p) in free.iter().zip(ring(free.len(), graph.centroid(), radius)) {
+        out[i] = p;
+    }
+    out
+}
+
+/// Places the movable nodes on a square grid, row by row in id order.
+pub fn grid(graph: &LayoutGraph) -> Vec<Vec2> {
+    let mut out = graph.positions.clone();
+    let free = graph.free();
+    let cols = (free.len() as f32).sqrt().ceil().max(1.) as usize;
+    let rows = free.len().div_ceil(cols);
+    let origin =
+        graph.centroid() - Vec2::new(cols as f32 - 1., -(rows as f32 - 1.)) * EDGE_LENGTH / 2.;
+    for (n, &i) in free.iter().enumerate() {
+        let (row, col) = (n / cols, n % cols);
+        out[i] = origin + Vec2::new(col as f32, -(row as f32)) * EDGE_LENGTH;
+    }
+    out
+}
+
+/// Scatters the movable nodes uniformly over a square sized so that each
+/// node gets about an edge length of room.
+pub fn random(graph: &LayoutGraph) -> Vec<Vec2> {
+    let mut rng = StdRng::seed_from_u64(graph.seed);
+    let mut out = graph.positions.clone();
+    let half = EDGE_LENGTH * (graph.len() as f32).sqrt() / 2.;
+    let center = graph.centroid();
+    for i in graph.free() {
+        out[i] = center
+            + Vec2::new(
+                rng.random_range(-half..=half),
+                rng.random_range(-half..=half),
+            );
+    }
+    out
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use super::{EDGE_LENGTH, LayoutGraph};
+
+const MAX_ITERATIONS: usize = 200;
+/// Stop once an iteration lowers the stress by less than this fraction.
+const TOLERANCE: f32 = 1e-4;
+
+/// Kamada–Kawai layout by stress majorization (SMACOF): node pairs are
+/// pulled towards a distance proportional to their graph-theoretic distance,
+/// each pair weighted by `1 / d²`. Disconnected nodes are treated as one hop
+/// further apart than the two most distant connected ones.
+///
+/// Needs the full distance matrix, so time and memory are O(n²); `compute`
+/// only calls this for up to [`STRESS_MAX_NODES`](super::STRESS_MAX_NODES)
+/// nodes.
+pub fn stress_majorization(graph: &LayoutGraph) -> Vec<Vec2> {
+    let n = graph.len();
+    let dist = distances(graph);
+    let mut pos = graph.spread_positions();
+    let mut prev_stress = f32::INFINITY;
+
+    for _ in 0..MAX_ITERATIONS {
+        for i in 0..n {
+            if graph.fixed[i] {
+                continue;
+            }
+            let mut sum = Vec2::ZERO;
+            let mut weights = 0.;
+            for j in 0..n {
+                if i == j {
+                    continue;
+                }
+                let d = dist[i * n + j];
+                let w = 1. / (d * d);
+                let delta = pos[i] - pos[j];
+                let len = delta.length();
+                let target = if len > 1e-6 {
+                    pos[j] + delta * (d / len)
+                } else {
+                    pos[j]
+                };
+                sum += target * w;
+                weights += w;
+            }
+            if weights > 0. {
+                pos[i] = sum / weights;
+            }
+        }
+
+        let stress = stress(&pos, &dist);
+        if prev_stress - stress < TOLERANCE * prev_stress {
+            break;
+        }
+        prev_stress = stress;
+    }
+    pos
+}
+
+/// Shortest-path lengths between all pairs in the undirected graph, in
+/// world units, as a row-major n × n matrix.
+fn distances(graph: &LayoutGraph) -> Vec<f32> {
+    let n = graph.len();
+    let adj = graph.neighbours();
+    let mut hops = vec![u32::MAX; n * n];
+    let mut queue = VecDeque::new();
+    for src in 0..n {
+        let row = &mut hops[src * n..(src + 1) * n];
+        row[src] = 0;
+        queue.push_back(src);
+        while let Some(u) = queue.pop_front() {
+            for &v in &adj[u] {
+                if row[v] == u32::MAX {
+                    row[v] = row[u] + 1;
+                    queue.push_back(v);
+                }
+            }
+        }
+    }
+    let unreachable = hops.iter().filter(|&&h| h != u32::MAX).max().unwrap_or(&0) + 1;
+    hops.into_iter()
+        .map(|h| h.min(unreachable) as f32 * EDGE_LENGTH)
+        .collect()
+}
+
+fn stress(pos: &[Vec2], dist: &[f32]) -> f32 {
+    let n = pos.len();
+    let mut total = 0.;
+    for i in 0..n {
+        for j in i + 1..n {
+            let d = dist[i * n + j];
+            let diff = pos[i].distance(pos[j]) - d;
+            total += diff * diff / (d * d);
+        }
+    }
+    total
+}
@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use super::{EDGE_LENGTH, LayoutGraph, LayoutResult, NODE_SPACING};
+
+/// Tidy tree drawing in the style of Reingold–Tilford, of a breadth-first
+/// spanning tree grown from `graph.root` along the edges' directions (both
+/// ways in undirected graphs). Nodes the root cannot reach start trees of
+/// their own, in id order, laid out side by side. The root keeps its
+/// position and the edges not in the tree come back dimmed.
+///
+/// Merging subtree contours costs O(depth) each, so the whole layout is
+/// O(n · depth).
+pub fn tree(graph: &LayoutGraph) -> LayoutResult {
+    if graph.len() == 0 {
+        return Vec::new().into();
+    }
+    let forest = Forest::new(graph);
+    let x = forest.tidy_x();
+    let offset = graph.positions[forest.roots[0]] - Vec2::new(x[forest.roots[0]], 0.);
+    let positions = (0..graph.len())
+        .map(|v| offset + Vec2::new(x[v], -(forest.depth[v] as f32) * EDGE_LENGTH))
+        .collect();
+    forest.result(positions)
+}
+
+/// The tidy tree bent into rings around the root: depth becomes the radius
+/// and the horizontal position the angle. With several trees, their roots
+/// share the first ring.
+pub fn radial_tree(graph: &LayoutGraph) -> LayoutResult {
+    if graph.len() == 0 {
+        return Vec::new().into();
+    }
+    let forest = Forest::new(graph);
+    let x = forest.tidy_x();
+    let (min, max) = x
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+    // a gap of NODE_SPACING closes the circle between the last and first
+    // leaf; the outer ring is made large enough to keep that spacing
+    let span = max - min + NODE_SPACING;
+    let extra = usize::from(forest.roots.len() > 1);
+    let max_depth = forest.depth.iter().max().map_or(0, |&d| d + extra).max(1);
+    let step = EDGE_LENGTH.max(span / TAU / max_depth as f32);
+
+    let center = if extra == 0 {
+        graph.positions[forest.roots[0]]
+    } else {
+        graph.centroid()
+    };
+    let positions = (0..graph.len())
+        .map(|v| {
+            let radius = (forest.depth[v] + extra) as f32 * step;
+            let angle = TAU / 4. - TAU * (x[v] - min) / span;
+            center + Vec2::from_angle(angle) * radius
+        })
+        .collect();
+    forest.result(positions)
+}
+
+/// Breadth-first spanning forest of the graph.
+struct Forest {
+    roots: Vec<usize>,
+    children: Vec<Vec<usize>>,
+    depth: Vec<usize>,
+    order: Vec<usize>,    // every node, each tree in breadth-first order
+    tree_edge: Vec<bool>, // per graph edge
+}
+
+impl Forest {
+    fn new(graph: &LayoutGraph) -> Self {
+        let n = graph.len();
+        let mut adj = vec![Vec::new(); n];
+        for (e, &(u, v, _)) in graph.edges.iter().enumerate() {
+            adj[u].push((v, e));
+            if !graph.directed {
+                adj[v].push((u, e));
+            }
+        }
+
+        let mut forest = Forest {
+            roots: Vec::new(),
+            children: vec![Vec::new(); n],
+            depth: vec![0; n],
+            order: Vec::with_capacity(n),
+            tree_edge: vec![false; graph.edges.len()],
+        };
+        let mut seen = vec![false; n];
+        let mut queue = VecDeque::new();
+        let starts = graph.root.into_iter().chain(0..n);
+        for root in starts {
+            if seen[root] {
+                continue;
+            }
+            seen[root] = true;
+            forest.roots.push(root);
+            queue.push_back(root);
+            while let Some(u) = queue.pop_front() {
+                forest.order.push(u);
+                for &(v, e) in &adj[u] {
+                    if !seen[v] {
+                        seen[v] = true;
+                        forest.children[u].push(v);
+                        forest.depth[v] = forest.depth[u] + 1;
+                        forest.tree_edge[e] = true;
+                        queue.push_back(v);
+                    }
+                }
+            }
+        }
+        forest
+    }
+
+    /// Horizontal position of every node. Subtrees are built bottom-up:
+    /// each child's subtree is pushed right until it clears the siblings
+    /// before it by `NODE_SPACING` at every depth, and the parent is
+    /// centred over its first and last child. The trees of the forest are
+    /// placed next to each other the same way.
+    fn tidy_x(&self) -> Vec<f32> {
+        let n = self.children.len();
+        let mut rel = vec![0.; n]; // position relative to the parent
+        // (leftmost, rightmost) position per depth below a node, relative
+        // to it; taken by the parent once merged
+        let mut contours: Vec<Vec<(f32, f32)>> = vec![Vec::new(); n];
+
+        // reversed breadth-first order visits children before parents
+        for &v in self.order.iter().rev() {
+            let children = &self.children[v];
+            let below = pack(children, &mut rel, &mut contours);
+            let mut contour = Vec::with_capacity(below.len() + 1);
+            contour.push((0., 0.));
+            contour.extend(below);
+            contours[v] = contour;
+        }
+        pack(&self.roots, &mut rel, &mut contours);
+
+        let mut x = vec![0.; n];
+        for &r in &self.roots {
+            x[r] = rel[r];
+        }
+        for &v in &self.order {
+            for &c in &self.children[v] {
+                x[c] = x[v] + rel[c];
+            }
+        }
+        x
+    }
+
+    fn result(&self, positions: Vec<Vec2>) -> LayoutResult {
+        LayoutResult {
+            positions,
+            routes: Vec::new(),
+            dimmed: self.tree_edge.iter().map(|&tree| !tree).collect(),
+        }
+    }
+}
+
+/// Places the subtrees rooted at `nodes` side by side, centred on 0: sets
+/// `rel` for each and returns their combined contour.
+fn pack(nodes: &[usize], rel: &mut [f32], contours: &mut [Vec<(f32, f32)>]) -> Vec<(f32, f32)> {
+    let mut combined: Vec<(f32, f32)> = Vec::new();
+    for &c in nodes {
+        let contour = std::mem::take(&mut contours[c]);
+        let shift = if combined.is_empty() {
+            0.
+        } else {
+            combined
+                .iter()
+                .zip(&contour)
+                .map(|(&(_, right), &(left, _))| right - left + NODE_SPACING)
+                .fold(f32::NEG_INFINITY, f32::max)
+        };
+        rel[c] = shift;
+        for (d, &(left, right)) in contour.iter().enumerate() {
+            match combined.get_mut(d) {
+                Some(level) => level.1 = right + shift,
+                None => combined.push((left + shift, right + shift)),
+            }
+        }
+    }
+    // centre over the first and last subtree
+    if let (Some(&first), Some(&last)) = (nodes.first(), nodes.last()) {
+        let middle = (rel[first] + rel[last]) / 2.;
+        for &c in nodes {
+            rel[c] -= middle;
+        }
+        for level in &mut combined {
+            level.0 -= middle;
+            level.1 -= middle;
+        }
+    }
+    combined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: Vec2 = Vec2::new(10., 20.);
+
+    fn graph(n: usize, edges: &[(usize, usize)], root: Option<usize>) -> LayoutGraph {
+        LayoutGraph {
+            positions: vec![ROOT; n],
+            fixed: vec![false; n],
+            edges: edges.iter().map(|&(u, v)| (u, v, 1.)).collect(),
+            directed: true,
+            root,
+            seed: 0,
+        }
+    }
+
+    /// Asserts that nodes at the same height are at least `NODE_SPACING`
+    /// apart.
+    fn assert_spaced(positions: &[Vec2]) {
+        for (i, a) in positions.iter().enumerate() {
+            for b in &positions[i + 1..] {
+                if (a.y - b.y).abs() < 1e-3 {
+                    assert!((a.x - b.x).abs() >= NODE_SPACING - 1e-3, "{a} and {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn siblings_are_spaced_under_their_parent() {
+        let out = tree(&graph(4, &[(0, 1), (0, 2), (0, 3)], Some(0)));
+        let p = &out.positions;
+        assert_eq!(p[0], ROOT);
+        for child in &p[1..4] {
+            assert_eq!(child.y, ROOT.y - EDGE_LENGTH);
+        }
+        assert_eq!(p[2].x - p[1].x, NODE_SPACING);
+        assert_eq!(p[3].x - p[2].x, NODE_SPACING);
+        assert_eq!(p[2].x, ROOT.x);
+    }
+
+    #[test]
+    fn packed_subtrees_do_not_overlap() {
+        // the first subtree widens further down than the second one, so the
+        // second must be pushed clear of it at its deepest level
+        let edges = [
+            (0, 1),
+            (0, 2),
+            (1, 3),
+            (1, 4),
+            (4, 5),
+            (4, 6),
+            (2, 7),
+            (7, 8),
+            (7, 9),
+        ];
+        let out = tree(&graph(10, &edges, Some(0)));
+        let p = &out.positions;
+        assert_spaced(p);
+        // every node of the second subtree is right of the first one's at
+        // the same depth
+        for a in [1, 3, 4, 5, 6] {
+            for b in [2, 7, 8, 9] {
+                if p[a].y == p[b].y {
+                    assert!(p[a].x < p[b].x, "{a} and {b}");
+                }
+            }
+        }
+        assert_eq!(p[0].x, (p[1].x + p[2].x) / 2.);
+    }
+
+    #[test]
+    fn trees_of_a_forest_sit_side_by_side() {
+        let out = tree(&graph(5, &[(0, 1), (0, 2), (3, 4)], None));
+        let p = &out.positions;
+        assert_eq!(p[0], ROOT);
+        assert_eq!(p[3].y, ROOT.y);
+        assert_eq!(p[4].y, ROOT.y - EDGE_LENGTH);
+        assert!(p[3].x - p[0].x >= NODE_SPACING);
+        assert!(p[4].x - p[2].x >= NODE_SPACING);
+        assert_spaced(p);
+    }
+
+    #[test]
+    fn radial_tree_puts_each_depth_on_its_own_ring() {
+        let edges = [(0, 1), (0, 2), (0, 3), (1, 4), (2, 5), (3, 6)];
+        let out = radial_tree(&graph(7, &edges, Some(0)));
+        let p = &out.positions;
+        assert_eq!(p[0], ROOT);
+        let ring = p[1].distance(ROOT);
+        assert!(ring >= EDGE_LENGTH);
+        for v in [1, 2, 3] {
+            assert!((p[v].distance(ROOT) - ring).abs() < 1e-2, "{v}");
+        }
+        for v in [4, 5, 6] {
+            assert!((p[v].distance(ROOT) - 2. * ring).abs() < 1e-2, "{v}");
+        }
+    }
+
+    #[test]
+    fn unreachable_nodes_start_their_own_tree() {
+        // 2 only points into the root's tree, so the root cannot reach it
+        let out = tree(&graph(3, &[(0, 1), (2, 1)], Some(0)));
+        let p = &out.positions;
+        assert_eq!(p[0], ROOT);
+        assert_eq!(p[1].y, ROOT.y - EDGE_LENGTH);
+        assert_eq!(p[2].y, ROOT.y);
+        assert!(p[2].x - p[0].x >= NODE_SPACING);
+        assert_eq!(out.dimmed, vec![false, true]);
+    }
+}
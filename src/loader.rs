@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use bevy::{color::palettes::css::*, prelude::*};
+use bevy_egui::{EguiContexts, egui};
+use rand::prelude::*;
+
+use crate::components::*;
+use crate::formats::{self, EdgeData, GraphData, NodeData};
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn open_graph(
+    mut requests: MessageReader<OpenGraph>,
+    mut commands: Commands,
+    assets: Res<GraphAssets>,
+    asset_server: Res<AssetServer>,
+    existing: Query<Entity, Or<(With<GNode>, With<GEdge>)>>,
+    mut graph: ResMut<Graph>,
+    mut colors: ResMut<NodeColors>,
+    mut file: ResMut<FileState>,
+    mut selected: ResMut<Selected>,
+    mut edge_state: ResMut<EdgeCreation>,
+    mut deletion: ResMut<DeletionRequest>,
+    mut manager: ResMut<LuaManager>,
+    mut config: ResMut<Config>,
+    mut history: ResMut<History>,
+    mut sim: ResMut<Simulation>,
+) {
+    let Some(OpenGraph { path }) = requests.read().last() else {
+        return;
+    };
+    let data = match formats::load(path) {
+        Ok(data) => data,
+        Err(e) => {
+            file.error = Some(format!("{}: {e}", path.display()));
+            return;
+        }
+    };
+
+    // Drop everything that refers to entities of the old graph
+    for ent in existing.iter() {
+        commands.entity(ent).despawn();
+    }
+    if let Some(temp_line) = edge_state.temp_line.take() {
+        commands.entity(temp_line).despawn();
+    }
+    *edge_state = EdgeCreation::default();
+    *deletion = DeletionRequest::default();
+    *graph = Graph::default();
+    colors.colors.clear();
+    selected.0 = None;
+    manager.active_script = None;
+    *history = History::default();
+    *sim = Simulation {
+        iterations: sim.iterations,
+        ..default()
+    };
+
+    spawn_graph(
+        &data,
+        &mut commands,
+        &assets,
+        &asset_server,
+        &mut graph,
+        &mut colors,
+        config.seed,
+    );
+    config.show_weights = data.edges.iter().any(|e| e.weight != 1.);
+    file.path = Some(path.clone());
+    file.error = None;
+}
+
+fn spawn_graph(
+    data: &GraphData,
+    commands: &mut Commands,
+    assets: &GraphAssets,
+    asset_server: &AssetServer,
+    graph: &mut Graph,
+    colors: &mut NodeColors,
+    seed: u64,
+) {
+    let mut node_map: HashMap<usize, Entity> = HashMap::new();
+    let mut rng = StdRng::seed_from_u64(seed);
+    graph.directed = data.directed;
+
+    for node in &data.nodes {
+        let pos = node.pos.unwrap_or_else(|| {
+            Vec2::new(
+                rng.random_range(-50f32..50f32),
+                rng.random_range(-50f32..50f32),
+            )
+        });
+        let ent = spawn_node(
+            commands,
+            assets,
+            asset_server,
+            node.id,
+            node.label.clone(),
+            pos,
+        );
+        if !node.attrs.is_empty() {
+            commands.entity(ent).insert(Attributes(node.attrs.clone()));
+        }
+        if node.pinned {
+            commands.entity(ent).insert(Pinned);
+        }
+        node_map.insert(node.id, ent);
+        graph.add_node(ent);
+        graph.curr_id = graph.curr_id.max(node.id);
+        colors
+            .colors
+            .insert(ent, node.color.unwrap_or(Color::from(BLACK)));
+    }
+
+    for edge in &data.edges {
+        let from = node_map[&edge.from];
+        let to = node_map[&edge.to];
+        let ent = spawn_edge(commands, assets, asset_server, from, to, edge.weight);
+        if !edge.attrs.is_empty() {
+            commands.entity(ent).insert(Attributes(edge.attrs.clone()));
+        }
+        graph.connect(ent, from, to, edge.weight);
+    }
+}
+
+pub fn spawn_node(
+    commands: &mut Commands,
+    assets: &GraphAssets,
+    asset_server: &AssetServer,
+    id: usize,
+    label: String,
+    pos: Vec2,
+) -> Entity {
+    let font = asset_server.load("fonts/FiraMono-Regular.ttf");
+    commands
+        .spawn((
+            Text2d::new(label.clone()),
+            GNode { id, label },
+            Mesh2d(assets.node_mesh.clone()),
+            MeshMaterial2d(assets.node_material.clone()),
+            Transform::from_translation(pos.extend(1.0)),
+            GlobalTransform::default(),
+            TextFont {
+                font,
+                font_size: 50.0,
+                ..default()
+            },
+        ))
+        .id()
+}
+
+pub fn spawn_edge(
+    commands: &mut Commands,
+    assets: &GraphAssets,
+    asset_server: &AssetServer,
+    from: Entity,
+    to: Entity,
+    weight: f32,
+) -> Entity {
+    let font = asset_server.load("fonts/FiraMono-Regular.ttf");
+    commands
+        .spawn((
+            GEdge { from, to, weight },
+            Transform::default(),
+            GlobalTransform::default(),
+            Visibility::default(),
+        ))
+        .with_children(|edge| {
+            edge.spawn((
+                EdgeLine(0),
+                Mesh2d(assets.edge_mesh.clone()),
+                MeshMaterial2d(assets.edge_material.clone()),
+                Transform::from_scale(Vec3::ZERO),
+            ));
+            edge.spawn((
+                EdgeArrow,
+                Mesh2d(assets.arrow_mesh.clone()),
+                MeshMaterial2d(assets.edge_material.clone()),
+                Transform::default(),
+                Visibility::Hidden,
+            ));
+            edge.spawn((
+                EdgeLabel,
+                Text2d::new(weight.to_string()),
+                TextFont {
+                    font,
+                    font_size: 30.0,
+                    ..default()
+                },
+                Transform::default(),
+            ));
+        })
+        .id()
+}
+
+/// Builds a [`GraphData`] snapshot of the graph currently in the world,
+/// including node positions.
+#[allow(clippy::type_complexity)]
+fn collect_graph(
+    nodes: &Query<(Entity, &GNode, &Transform, Has<Pinned>, Option<&Attributes>)>,
+    edges: &Query<(&GEdge, Option<&Attributes>)>,
+    graph: &Graph,
+    colors: &NodeColors,
+) -> GraphData {
+    let mut ids = HashMap::new();
+    let mut data = GraphData {
+        directed: graph.directed,
+        ..default()
+    };
+    for (ent, node, tf, pinned, attrs) in nodes.iter() {
+        ids.insert(ent, node.id);
+        data.nodes.push(NodeData {
+            id: node.id,
+            label: node.label.clone(),
+            pos: Some(tf.translation.truncate()),
+            color: colors.colors.get(&ent).copied(),
+            pinned,
+            attrs: attrs.map(|a| a.0.clone()).unwrap_or_default(),
+        });
+    }
+    data.nodes.sort_by_key(|n| n.id);
+
+    for (edge, attrs) in graph.edges.iter().filter_map(|&e| edges.get(e).ok()) {
+        if let (Some(&from), Some(&to)) = (ids.get(&edge.from), ids.get(&edge.to)) {
+            data.edges.push(EdgeData {
+                from,
+                to,
+                weight: edge.weight,
+                attrs: attrs.map(|a| a.0.clone()).unwrap_or_default(),
+            });
+        }
+    }
+    data
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn file_ui(
+    mut egui_ctx: EguiContexts,
+    mut file: ResMut<FileState>,
+    mut open: MessageWriter<OpenGraph>,
+    nodes: Query<(Entity, &GNode, &Transform, Has<Pinned>, Option<&Attributes>)>,
+    edges: Query<(&GEdge, Option<&Attributes>)>,
+    graph: Res<Graph>,
+    colors: Res<NodeColors>,
+    keys: Res<ButtonInput<KeyCode>>,
+) -> Result {
+    let mut save_to = None;
+
+    egui::Window::new("File").show(egui_ctx.ctx_mut()?, |ui| {
+        match &file.path {
+            Some(path) => ui.label(path.display().to_string()),
+            None => ui.label("(unsaved graph)"),
+        };
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(file.path.is_some(), egui::Button::new("Save"))
+                .clicked()
+            {
+                save_to = file.path.clone();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut file.path_input);
+            let input = file.path_input.trim();
+            if ui.button("Open").clicked() && !input.is_empty() {
+                open.write(OpenGraph { path: input.into() });
+            }
+            if ui.button("Save As").clicked() && !input.is_empty() {
+                save_to = Some(input.into());
+            }
+        });
+        ui.label("Format is chosen by extension: .graphml, .dot/.gv, .json, anything else is an edge list.");
+    });
+
+    // Ctrl+S typed into a text field is not a save
+    let typing = egui_ctx.ctx_mut()?.wants_keyboard_input();
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if !typing && ctrl && keys.just_pressed(KeyCode::KeyS) {
+        save_to = file.path.clone();
+    }
+
+    if let Some(path) = save_to {
+        let data = collect_graph(&nodes, &edges, &graph, &colors);
+        match formats::save(&path, &data) {
+            Ok(()) => {
+                file.path = Some(path);
+                file.error = None;
+            }
+            Err(e) => file.error = Some(format!("{}: {e}", path.display())),
+        }
+    }
+
+    if let Some(error) = file.error.clone() {
+        egui::Window::new("File error")
+            .collapsible(false)
+            .show(egui_ctx.ctx_mut()?, |ui| {
+                ui.colored_label(egui::Color32::RED, error);
+                if ui.button("Dismiss").clicked() {
+                    file.error = None;
+                }
+            });
+    }
+    Ok(())
+}
@@ -1,91 +1,124 @@
-use bevy::a11y::ManageAccessibilityUpdates;
 use bevy::window::PrimaryWindow;
 use bevy::{color::palettes::css::*, prelude::*};
 use bevy_egui::{EguiContexts, EguiPlugin, EguiPrimaryContextPass, egui};
 use bevy_pancam::{PanCam, PanCamPlugin};
-use rand::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 
+mod cli;
 pub mod components;
+mod editor;
+pub mod formats;
+mod history;
+mod layout;
+mod loader;
 pub mod physics;
 mod scripts;
 
+use crate::cli::CliError;
 use crate::components::*;
+use crate::formats::{EdgeData, NodeData};
+use crate::history::*;
+use crate::loader::*;
 use crate::scripts::*;
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Grephite".to_string(),
-                ..default()
-            }),
+    let cli = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(CliError::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(CliError::Invalid(msg)) => {
+            eprintln!("grephite: {msg}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "Grephite".to_string(),
             ..default()
-        }))
-        .add_plugins(PanCamPlugin::default())
-        .add_plugins(EguiPlugin::default())
-        .insert_state(AppMode::View)
-        .insert_resource(DragState::default())
-        .insert_resource(Config {
-            k_r: 5000.,
-            k_g: 0.2,
-            enabled: true,
-            scripts_dir: "scripts".to_string(),
-        })
-        .insert_resource(Selected(None))
-        .insert_resource(Graph::default())
-        .insert_resource(EdgeCreation::default())
-        .insert_resource(DeletionRequest::default())
-        .insert_resource(NodeColors {
-            colors: HashMap::new(),
-        })
-        .insert_resource(LuaManager::default())
-        .add_message::<ScriptCommand>()
-        .add_message::<StepLua>()
-        .add_message::<ExecLuaScript>()
-        .add_systems(Startup, (load_edge_list,).chain())
-        .add_systems(
-            Update,
+        }),
+        ..default()
+    }))
+    .add_plugins(PanCamPlugin)
+    .add_plugins(EguiPlugin::default())
+    .insert_state(cli.mode)
+    .insert_resource(DragState::default())
+    .insert_resource(cli.config)
+    .insert_resource(FileState::default())
+    .insert_resource(Selected(None))
+    .insert_resource(Graph::default())
+    .insert_resource(EdgeCreation::default())
+    .insert_resource(DeletionRequest::default())
+    .init_resource::<ContextMenu>()
+    .insert_resource(NodeColors {
+        colors: HashMap::new(),
+    })
+    .insert_resource(LuaManager::default())
+    .init_resource::<ScriptConsole>()
+    .init_resource::<ScriptEditor>()
+    .insert_resource(History::default())
+    .init_resource::<GraphAssets>()
+    .init_resource::<LayoutState>()
+    .init_resource::<Simulation>()
+    .add_message::<ScriptCommand>()
+    .add_message::<StepLua>()
+    .add_message::<ExecLuaScript>()
+    .add_message::<EvalLua>()
+    .add_message::<OpenGraph>()
+    .add_message::<HistoryStep>()
+    .add_message::<RunLayout>()
+    .add_systems(Startup, spawn_camera)
+    .add_systems(
+        Update,
+        (
+            open_graph,
+            (history_keys, apply_history).chain(),
+            draw_edges,
+            crate::physics::apply_forces,
+            (layout::start_layout, layout::animate_layout).chain(),
+            pan_camera_system,
+            drag_nodes,
+            toggle_pin,
+            draw_nodes,
+            mark_pinned,
+            create_node.run_if(in_state(AppMode::Edit)),
+            create_edge.run_if(in_state(AppMode::Edit)),
+            draw_edge_preview.run_if(in_state(AppMode::Edit)),
+            detect_right_clicks.run_if(in_state(AppMode::Edit)),
             (
-                draw_edges,
-                crate::physics::apply_forces,
-                pan_camera_system,
-                drag_nodes,
-                draw_nodes,
-                create_node.run_if(in_state(AppMode::Edit)),
-                create_edge.run_if(in_state(AppMode::Edit)),
-                draw_edge_preview.run_if(in_state(AppMode::Edit)),
-                detect_right_clicks.run_if(in_state(AppMode::Edit)),
                 spawn_lua_scripts,
                 run_lua_scripts,
+                eval_lua_repl,
                 flush_lua_events,
                 exec_lua_events,
-                auto_run,
-            ),
-        )
-        .add_systems(
-            EguiPrimaryContextPass,
-            (
-                ui_system,
-                deletion_popup.run_if(in_state(AppMode::Edit)),
-                script_ui.run_if(in_state(AppMode::Script)),
-            ),
-        )
-        .run();
-}
+            )
+                .chain(),
+            auto_run,
+        ),
+    )
+    .add_systems(
+        EguiPrimaryContextPass,
+        (
+            ui_system,
+            file_ui,
+            context_menu.run_if(in_state(AppMode::Edit)),
+            deletion_popup.run_if(in_state(AppMode::Edit)),
+            script_ui.run_if(in_state(AppMode::Script)),
+            editor::script_editor.run_if(in_state(AppMode::Script)),
+        ),
+    );
 
-fn compute_degrees(edge_query: &Query<&GEdge>) -> HashMap<Entity, usize> {
-    let mut degrees = HashMap::new();
-
-    for edge in edge_query.iter() {
-        *degrees.entry(edge.from).or_insert(0) += 1;
-        *degrees.entry(edge.to).or_insert(0) += 1;
+    if let Some(path) = cli.path {
+        app.world_mut().write_message(OpenGraph { path });
     }
-
-    degrees
+    app.run();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn drag_nodes(
     mut drag: ResMut<DragState>,
     mut camera: Query<(&Camera, &GlobalTransform, &mut PanCam)>,
@@ -95,6 +128,7 @@ fn drag_nodes(
     window: Query<&Window, With<PrimaryWindow>>,
     mut selected: ResMut<Selected>,
     egui_ctx: EguiContexts,
+    mut history: ResMut<History>,
 ) -> Result {
     if egui_ctx.ctx()?.wants_pointer_input() {
         return Ok(());
@@ -120,7 +154,8 @@ fn drag_nodes(
                 drag.dragging = Some(ent);
                 camera.2.enabled = false;
                 drag.offset = tf.translation.truncate() - *world_pos;
-                if selected.0 == None {
+                drag.start = tf.translation.truncate();
+                if selected.0.is_none() {
                     selected.0 = Some(ent);
                 } else {
                     selected.0 = None;
@@ -135,20 +170,52 @@ fn drag_nodes(
     }
 
     // update drag
-    if let Some(ent) = drag.dragging {
-        if let Ok((_, mut tf, _)) = nodes.get_mut(ent) {
-            tf.translation = (*world_pos + drag.offset).extend(tf.translation.z);
-        }
+    if let Some(ent) = drag.dragging
+        && let Ok((_, mut tf, _)) = nodes.get_mut(ent)
+    {
+        tf.translation = (*world_pos + drag.offset).extend(tf.translation.z);
     }
 
     // release drag
     if mouse_input.just_released(MouseButton::Left) {
+        if let Some(ent) = drag.dragging
+            && let Ok((_, tf, node)) = nodes.get(ent)
+        {
+            let to = tf.translation.truncate();
+            if to != drag.start {
+                history.push(Edit::MoveNode {
+                    id: node.id,
+                    from: drag.start,
+                    to,
+                });
+            }
+        }
         camera.2.enabled = true;
         drag.dragging = None;
     }
     Ok(())
 }
 
+/// P pins the selected node in place, or unpins it.
+fn toggle_pin(
+    keys: Res<ButtonInput<KeyCode>>,
+    selected: Res<Selected>,
+    nodes: Query<Has<Pinned>, With<GNode>>,
+    mut commands: Commands,
+    mut egui_ctx: EguiContexts,
+) -> Result {
+    if egui_ctx.ctx_mut()?.wants_keyboard_input() || !keys.just_pressed(KeyCode::KeyP) {
+        return Ok(());
+    }
+    if let Some(node) = selected.0
+        && let Ok(pinned) = nodes.get(node)
+    {
+        set_pinned(&mut commands, node, !pinned);
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 fn create_node(
     mut camera: Query<(&Camera, &GlobalTransform)>,
     nodes: Query<(Entity, &Transform, &GNode)>,
@@ -157,11 +224,11 @@ fn create_node(
     egui_ctx: EguiContexts,
     mut world_pos: Local<Vec2>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    assets: Res<GraphAssets>,
     asset_server: Res<AssetServer>,
     mouse_input: Res<ButtonInput<MouseButton>>,
     mut colors: ResMut<NodeColors>,
+    mut history: ResMut<History>,
 ) -> Result {
     if egui_ctx.ctx()?.wants_pointer_input() {
         return Ok(());
@@ -179,7 +246,7 @@ fn create_node(
         *world_pos = world_position;
     }
     let mut on = false;
-    for (ent, tf, _) in nodes.iter() {
+    for (_, tf, _) in nodes.iter() {
         let dist = (tf.translation.truncate() - *world_pos).length();
         if dist < 60.0 {
             on = true;
@@ -189,39 +256,40 @@ fn create_node(
         return Ok(());
     }
 
-    let font = asset_server.load("fonts/FiraMono-Regular.ttf");
-    let text_font = TextFont {
-        font: font.clone(),
-        font_size: 50.0,
-        ..default()
-    };
     graph.curr_id += 1;
-    let id = commands
-        .spawn((
-            GNode { id: graph.curr_id },
-            Mesh2d(meshes.add(Circle::new(50.))),
-            MeshMaterial2d(materials.add(Color::from(BLACK))),
-            Transform::from_translation(Vec3::new(world_pos.x, world_pos.y, 1.0)),
-            GlobalTransform::default(),
-            Text2d::new(graph.curr_id.to_string()),
-            text_font.clone(),
-        ))
-        .id();
-    graph.adj.insert(id, Vec::new());
+    let id = spawn_node(
+        &mut commands,
+        &assets,
+        &asset_server,
+        graph.curr_id,
+        graph.curr_id.to_string(),
+        *world_pos,
+    );
+    graph.add_node(id);
     colors.colors.insert(id, Color::from(BLACK));
+    history.push(Edit::AddNode(
+        NodeData {
+            pos: Some(*world_pos),
+            color: Some(Color::from(BLACK)),
+            ..NodeData::new(graph.curr_id)
+        },
+        Vec::new(),
+    ));
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_edge(
     mut commands: Commands,
     mut edge_state: ResMut<EdgeCreation>,
-    nodes: Query<(Entity, &Transform), With<GNode>>,
+    nodes: Query<(Entity, &Transform, &GNode)>,
     mouse: Res<ButtonInput<MouseButton>>,
     window: Query<&Window, With<PrimaryWindow>>,
     camera: Query<(&Camera, &GlobalTransform)>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    assets: Res<GraphAssets>,
+    asset_server: Res<AssetServer>,
     mut graph: ResMut<Graph>,
+    mut history: ResMut<History>,
 ) -> Result {
     if !mouse.just_pressed(MouseButton::Left) {
         return Ok(());
@@ -242,8 +310,8 @@ fn create_edge(
     // Did we click on a node?
     let clicked_node = nodes
         .iter()
-        .find(|(_, tf)| (tf.translation.truncate() - world_pos).length() < 60.0)
-        .map(|(e, _)| e);
+        .find(|(_, tf, _)| (tf.translation.truncate() - world_pos).length() < 60.0)
+        .map(|(e, _, _)| e);
 
     if let Some(node) = clicked_node {
         match edge_state.from {
@@ -253,17 +321,12 @@ fn create_edge(
             }
             Some(from) if from != node => {
                 // second click — finalize edge
-                let edge = commands
-                    .spawn((
-                        GEdge { from, to: node },
-                        Mesh2d(meshes.add(Rectangle::new(0., 0.))),
-                        MeshMaterial2d(materials.add(ColorMaterial::from(Color::from(RED)))),
-                        Transform::default(),
-                        GlobalTransform::default(),
-                    ))
-                    .id();
-                graph.adj.entry(from).or_insert(Vec::new()).push(node);
-                graph.adj.entry(node).or_insert(Vec::new()).push(from);
+                let nth = graph.edge_count(from, node);
+                let edge = spawn_edge(&mut commands, &assets, &asset_server, from, node, 1.);
+                graph.connect(edge, from, node, 1.);
+                if let (Ok((_, _, a)), Ok((_, _, b))) = (nodes.get(from), nodes.get(node)) {
+                    history.push(Edit::AddEdge(EdgeData::new(a.id, b.id), nth));
+                }
                 commands.entity(edge_state.temp_line.unwrap()).despawn();
                 edge_state.from = None;
                 edge_state.temp_line = None;
@@ -286,9 +349,8 @@ fn draw_edge_preview(
     nodes: Query<&Transform, With<GNode>>,
     window: Query<&Window, With<PrimaryWindow>>,
     camera: Query<(&Camera, &GlobalTransform)>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    mut temp_query: Query<(&mut Transform, &mut Mesh2d), Without<GNode>>,
+    assets: Res<GraphAssets>,
+    mut temp_query: Query<&mut Transform, Without<GNode>>,
 ) -> Result {
     if let Some(from) = edge_state.from {
         let (camera, camera_tf) = camera.single()?;
@@ -313,28 +375,29 @@ fn draw_edge_preview(
             let temp_line = edge_state.temp_line.get_or_insert_with(|| {
                 commands
                     .spawn((
-                        Mesh2d(meshes.add(Rectangle::new(length, 2.))),
-                        MeshMaterial2d(materials.add(ColorMaterial::from(Color::from(GRAY)))),
+                        Mesh2d(assets.edge_mesh.clone()),
+                        MeshMaterial2d(assets.preview_material.clone()),
                         Transform::from_translation(Vec3::new(
                             (start.x + world_pos.x) / 2.0,
                             (start.y + world_pos.y) / 2.0,
                             0.0,
                         ))
-                        .with_rotation(Quat::from_rotation_z(angle)),
+                        .with_rotation(Quat::from_rotation_z(angle))
+                        .with_scale(Vec3::new(length, 2., 1.)),
                         GlobalTransform::default(),
                     ))
                     .id()
             });
 
             // Update existing line
-            if let Ok((mut tf, mut mesh2d)) = temp_query.get_mut(*temp_line) {
+            if let Ok(mut tf) = temp_query.get_mut(*temp_line) {
                 tf.translation = Vec3::new(
                     (start.x + world_pos.x) / 2.0,
                     (start.y + world_pos.y) / 2.0,
                     0.0,
                 );
                 tf.rotation = Quat::from_rotation_z(angle);
-                *mesh2d = Mesh2d(meshes.add(Rectangle::new(length, 2.)));
+                tf.scale = Vec3::new(length, 2., 1.);
             }
         }
     } else if let Some(temp_line) = edge_state.temp_line.take() {
@@ -344,146 +407,213 @@ fn draw_edge_preview(
     Ok(())
 }
 
-fn load_edge_list(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    asset_server: Res<AssetServer>,
-    mut graph: ResMut<Graph>,
-    mut colors: ResMut<NodeColors>,
-) -> Result {
-    let content = fs::read_to_string("graph.edges").expect("Unable to read file");
-    let mut node_map: HashMap<usize, Entity> = HashMap::new();
-    let font = asset_server.load("fonts/FiraMono-Regular.ttf");
-    let mut rng = rand::rng();
-    let text_font = TextFont {
-        font: font.clone(),
-        font_size: 50.0,
-        ..default()
-    };
-
-    // Camera
-    commands.spawn((Camera2d::default(), PanCam::default()));
-
-    for line in content.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 2 {
-            continue;
-        }
-        let from_id = parts[0].to_string().parse()?;
-        let to_id = parts[1].to_string().parse()?;
-        graph.curr_id = graph.curr_id.max(from_id).max(to_id);
-
-        let from_ent = *node_map.entry(from_id).or_insert_with(|| {
-            let ent = commands
-                .spawn((
-                    GNode { id: from_id },
-                    Mesh2d(meshes.add(Circle::new(50.))),
-                    MeshMaterial2d(materials.add(Color::from(BLACK))),
-                    Transform::from_translation(Vec3::new(
-                        rng.random_range(-50f32..50f32),
-                        rng.random_range(-50f32..50f32),
-                        1.0,
-                    )),
-                    GlobalTransform::default(),
-                    Text2d::new(from_id.to_string()),
-                    text_font.clone(),
-                ))
-                .id();
-
-            ent
-        });
-
-        let to_ent = *node_map.entry(to_id).or_insert_with(|| {
-            let ent = commands
-                .spawn((
-                    Text2d::new(to_id.to_string()),
-                    text_font.clone(),
-                    GNode { id: to_id },
-                    Mesh2d(meshes.add(Circle::new(50.))),
-                    MeshMaterial2d(materials.add(Color::from(BLACK))),
-                    Transform::from_translation(Vec3::new(
-                        rng.random_range(-50f32..50f32),
-                        rng.random_range(-50f32..50f32),
-                        1.0,
-                    )),
-                    GlobalTransform::default(),
-                ))
-                .id();
-            ent
-        });
-        graph.adj.entry(from_ent).or_insert(Vec::new()).push(to_ent);
-        graph.adj.entry(to_ent).or_insert(Vec::new()).push(from_ent);
-        let ent = commands
-            .spawn((
-                GEdge {
-                    from: from_ent,
-                    to: to_ent,
-                },
-                Mesh2d(meshes.add(Rectangle::new(0., 0.))),
-                MeshMaterial2d(materials.add(ColorMaterial::from(Color::from(RED)))),
-                Transform::default(),
-                GlobalTransform::default(),
-            ))
-            .id();
-        graph.edges.push(ent);
-        colors.colors.insert(to_ent, Color::from(BLACK));
-        colors.colors.insert(from_ent, Color::from(BLACK));
-    }
-    Ok(())
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn((Camera2d, PanCam::default()));
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn draw_edges(
+    mut commands: Commands,
+    assets: Res<GraphAssets>,
     query_nodes: Query<(&Transform, &GNode), Without<GEdge>>,
-    mut query_edges: Query<(&mut Transform, &mut Mesh2d, &GEdge)>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    query_edges: Query<
+        (
+            Entity,
+            Ref<GEdge>,
+            &Children,
+            Option<&EdgeRoute>,
+            Has<Dimmed>,
+        ),
+        Without<GNode>,
+    >,
+    mut lines: Query<
+        (
+            &EdgeLine,
+            &mut Transform,
+            &mut Visibility,
+            &mut MeshMaterial2d<ColorMaterial>,
+        ),
+        (
+            Without<EdgeArrow>,
+            Without<EdgeLabel>,
+            Without<GNode>,
+            Without<GEdge>,
+        ),
+    >,
+    mut labels: Query<
+        (&mut Transform, &mut Text2d, &mut Visibility),
+        (With<EdgeLabel>, Without<GNode>, Without<GEdge>),
+    >,
+    mut arrows: Query<
+        (
+            &mut Transform,
+            &mut Visibility,
+            &mut MeshMaterial2d<ColorMaterial>,
+        ),
+        (
+            With<EdgeArrow>,
+            Without<EdgeLabel>,
+            Without<GNode>,
+            Without<GEdge>,
+        ),
+    >,
+    config: Res<Config>,
+    graph: Res<Graph>,
 ) {
-    for (mut transform, mut mesh2d, edge) in query_edges.iter_mut() {
-        if let (Ok((from_tf, _)), Ok((to_tf, _))) =
+    // Only write components whose value changes, so that edges between
+    // nodes at rest are not re-propagated and re-extracted every frame.
+    let visible = |shown: bool| {
+        if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        }
+    };
+    // The edge entity itself stays at the origin, so its children are
+    // placed in world coordinates.
+    for (entity, edge, children, route, dimmed) in query_edges.iter() {
+        let (Ok((from_tf, _)), Ok((to_tf, _))) =
             (query_nodes.get(edge.from), query_nodes.get(edge.to))
-        {
-            let start = from_tf.translation.truncate();
-            let end = to_tf.translation.truncate();
-            let direction = end - start;
-            let length = direction.length();
-            let angle = direction.y.atan2(direction.x);
+        else {
+            continue;
+        };
+        let path = EdgeRoute::path(
+            route,
+            from_tf.translation.truncate(),
+            to_tf.translation.truncate(),
+        );
+        let segments: Vec<_> = path.windows(2).map(|w| (w[0], w[1])).collect();
+        let material = if dimmed {
+            &assets.dimmed_material
+        } else {
+            &assets.edge_material
+        };
+        let mut drawn = 0;
+
+        for child in children.iter() {
+            // Stretch the shared unit square along each segment, hiding
+            // segments left over from a route with more bends
+            if let Ok((line, mut line_tf, mut visibility, mut line_mat)) = lines.get_mut(child) {
+                visibility.set_if_neq(visible(line.0 < segments.len()));
+                if &line_mat.0 != material {
+                    line_mat.0 = material.clone();
+                }
+                if let Some(&(a, b)) = segments.get(line.0) {
+                    line_tf.set_if_neq(segment_transform(a, b));
+                    drawn += 1;
+                }
+            }
+
+            // Put the arrowhead's tip on the target node's circle
+            if let Ok((mut arrow_tf, mut visibility, mut arrow_mat)) = arrows.get_mut(child) {
+                visibility.set_if_neq(visible(graph.directed));
+                if &arrow_mat.0 != material {
+                    arrow_mat.0 = material.clone();
+                }
+                let (a, b) = segments[segments.len() - 1];
+                let direction = (b - a).normalize_or_zero();
+                arrow_tf.set_if_neq(Transform {
+                    translation: (b - direction * NODE_RADIUS).extend(0.5),
+                    rotation: Quat::from_rotation_z(direction.to_angle()),
+                    ..default()
+                });
+            }
 
-            let mesh = meshes.add(Mesh::from(Rectangle::new(length, 2.)));
-            *mesh2d = Mesh2d(mesh);
-            *transform = Transform {
-                translation: Vec3::new((start.x + end.x) / 2.0, (start.y + end.y) / 2.0, 0.0),
-                rotation: Quat::from_rotation_z(angle),
-                ..default()
+            // Keep the weight label just above the middle segment
+            let Ok((mut label_tf, mut text, mut visibility)) = labels.get_mut(child) else {
+                continue;
             };
+            visibility.set_if_neq(visible(config.show_weights));
+            if edge.is_changed() {
+                let weight = edge.weight.to_string();
+                if text.0 != weight {
+                    text.0 = weight;
+                }
+            }
+            let (a, b) = segments[segments.len() / 2];
+            label_tf.set_if_neq(Transform::from_translation(
+                ((a + b) / 2. + Vec2::new(0., 20.)).extend(0.5),
+            ));
         }
+
+        // Routes with more bends than ever before need more segments
+        for (index, &(a, b)) in segments.iter().enumerate().skip(drawn) {
+            commands.entity(entity).with_child((
+                EdgeLine(index),
+                Mesh2d(assets.edge_mesh.clone()),
+                MeshMaterial2d(material.clone()),
+                segment_transform(a, b),
+            ));
+        }
+    }
+}
+
+/// Transform stretching the unit edge mesh from `a` to `b`.
+fn segment_transform(a: Vec2, b: Vec2) -> Transform {
+    let direction = b - a;
+    Transform {
+        translation: ((a + b) / 2.).extend(0.),
+        rotation: Quat::from_rotation_z(direction.to_angle()),
+        scale: Vec3::new(direction.length(), 2., 1.),
     }
 }
 
 fn draw_nodes(
     mut query_nodes: Query<(Entity, &mut MeshMaterial2d<ColorMaterial>), With<GNode>>,
+    added: Query<(), Added<GNode>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    mut assets: ResMut<GraphAssets>,
     selected: Res<Selected>,
     colors: Res<NodeColors>,
 ) {
-    // Reset all nodes to BLACK
+    if !colors.is_changed() && !selected.is_changed() && added.is_empty() {
+        return;
+    }
     for (entity, mut mat) in query_nodes.iter_mut() {
-        if Some(entity) == selected.0 {
-            *mat = MeshMaterial2d(materials.add(Color::from(RED)));
+        let color = if Some(entity) == selected.0 {
+            Color::from(RED)
         } else {
-            *mat = MeshMaterial2d(
-                materials.add(*colors.colors.get(&entity).unwrap_or(&Color::from(BLACK))),
-            );
+            *colors.colors.get(&entity).unwrap_or(&Color::from(BLACK))
+        };
+        let handle = assets.material(&mut materials, color);
+        if mat.0 != handle {
+            mat.0 = handle;
         }
     }
 }
 
+/// Puts a `PinMarker` ring around nodes when they are pinned and takes it
+/// away when they are unpinned.
+fn mark_pinned(
+    mut commands: Commands,
+    assets: Res<GraphAssets>,
+    pinned: Query<Entity, Added<Pinned>>,
+    mut unpinned: RemovedComponents<Pinned>,
+    markers: Query<(Entity, &ChildOf), With<PinMarker>>,
+) {
+    let unpinned: HashSet<Entity> = unpinned.read().collect();
+    for (marker, parent) in markers.iter() {
+        if unpinned.contains(&parent.parent()) {
+            commands.entity(marker).try_despawn();
+        }
+    }
+    for node in pinned.iter() {
+        commands.entity(node).with_child((
+            PinMarker,
+            Mesh2d(assets.pin_mesh.clone()),
+            MeshMaterial2d(assets.pin_material.clone()),
+            Transform::from_xyz(0., 0., -0.1),
+        ));
+    }
+}
+
 fn detect_right_clicks(
     mouse: Res<ButtonInput<MouseButton>>,
     window: Query<&Window, With<PrimaryWindow>>,
     camera: Query<(&Camera, &GlobalTransform)>,
     nodes: Query<(Entity, &Transform), With<GNode>>,
-    edges: Query<(Entity, &GEdge, &Transform)>,
-    mut deletion: ResMut<DeletionRequest>,
+    edges: Query<(Entity, &GEdge, Option<&EdgeRoute>)>,
+    mut menu: ResMut<ContextMenu>,
 ) -> Result {
     if !mouse.just_pressed(MouseButton::Right) {
         return Ok(());
@@ -504,18 +634,28 @@ fn detect_right_clicks(
     // 1️⃣ Check nodes first
     for (ent, tf) in nodes.iter() {
         if (tf.translation.truncate() - world_pos).length() < 60.0 {
-            deletion.node = Some(ent);
+            *menu = ContextMenu {
+                node: Some(ent),
+                edge: None,
+            };
             return Ok(()); // found a node
         }
     }
 
     // 2️⃣ Otherwise check edges (approximate with distance to line)
-    for (ent, edge, _) in edges.iter() {
+    for (ent, edge, route) in edges.iter() {
         if let (Ok(from_tf), Ok(to_tf)) = (nodes.get(edge.from), nodes.get(edge.to)) {
             let a = from_tf.1.translation.truncate();
             let b = to_tf.1.translation.truncate();
-            if point_near_segment(a, b, world_pos, 5.0) {
-                deletion.edge = Some(ent);
+            let path = EdgeRoute::path(route, a, b);
+            if path
+                .windows(2)
+                .any(|w| point_near_segment(w[0], w[1], world_pos, 5.0))
+            {
+                *menu = ContextMenu {
+                    node: None,
+                    edge: Some(ent),
+                };
                 return Ok(());
             }
         }
@@ -530,12 +670,106 @@ fn point_near_segment(a: Vec2, b: Vec2, p: Vec2, tolerance: f32) -> bool {
     let closest = a + t * ab;
     p.distance(closest) < tolerance
 }
+#[allow(clippy::too_many_arguments)]
+/// Popup for the right-clicked node or edge: pinning the node or the edge's
+/// weight, and a way to delete it.
+fn context_menu(
+    mut egui_ctx: EguiContexts,
+    mut menu: ResMut<ContextMenu>,
+    mut deletion: ResMut<DeletionRequest>,
+    mut commands: Commands,
+    mut graph: ResMut<Graph>,
+    nodes: Query<Has<Pinned>, With<GNode>>,
+    edges: Query<(Entity, &GEdge)>,
+    ids: Query<&GNode>,
+    mut history: ResMut<History>,
+) -> Result {
+    if let Some(node_ent) = menu.node {
+        // the node may have been deleted or undone since
+        let Ok(pinned) = nodes.get(node_ent) else {
+            menu.node = None;
+            return Ok(());
+        };
+        egui::Window::new("Node")
+            .collapsible(false)
+            .show(egui_ctx.ctx_mut()?, |ui| {
+                let mut pin = pinned;
+                if ui
+                    .checkbox(&mut pin, "Pinned")
+                    .on_hover_text("P pins or unpins the selected node")
+                    .changed()
+                {
+                    set_pinned(&mut commands, node_ent, pin);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Close").clicked() {
+                        menu.node = None;
+                    }
+                    if ui.button("Delete...").clicked() {
+                        deletion.node = Some(node_ent);
+                        menu.node = None;
+                    }
+                });
+            });
+    }
+
+    let Some(edge_ent) = menu.edge else {
+        return Ok(());
+    };
+    // the edge may have been deleted or undone since
+    let Ok((_, edge)) = edges.get(edge_ent) else {
+        menu.edge = None;
+        return Ok(());
+    };
+    egui::Window::new("Edge")
+        .collapsible(false)
+        .show(egui_ctx.ctx_mut()?, |ui| {
+            let mut weight = edge.weight;
+            ui.horizontal(|ui| {
+                ui.label("Weight");
+                // a typed-in `inf` or `nan` would wreck the simulation
+                if ui
+                    .add(egui::DragValue::new(&mut weight).speed(0.1))
+                    .changed()
+                    && weight.is_finite()
+                {
+                    commands.entity(edge_ent).insert(GEdge {
+                        from: edge.from,
+                        to: edge.to,
+                        weight,
+                    });
+                    graph.set_weight(edge_ent, weight);
+                    if let (Ok(a), Ok(b)) = (ids.get(edge.from), ids.get(edge.to)) {
+                        let nth = parallel_index(&graph, &edges, edge_ent);
+                        history.push_weight(a.id, b.id, nth, edge.weight, weight);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Close").clicked() {
+                    menu.edge = None;
+                }
+                if ui.button("Delete...").clicked() {
+                    deletion.edge = Some(edge_ent);
+                    menu.edge = None;
+                }
+            });
+        });
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 fn deletion_popup(
     mut egui_ctx: EguiContexts,
     mut deletion: ResMut<DeletionRequest>,
     mut commands: Commands,
     mut graph: ResMut<Graph>,
     edges: Query<(Entity, &GEdge)>,
+    ids: Query<&GNode>,
+    nodes: Query<(&Transform, Has<Pinned>), With<GNode>>,
+    attrs: Query<&Attributes>,
+    colors: Res<NodeColors>,
+    mut history: ResMut<History>,
 ) -> Result {
     if let Some(node_ent) = deletion.node {
         egui::Window::new("Delete Node?")
@@ -547,8 +781,13 @@ fn deletion_popup(
                         deletion.node = None;
                     }
                     if ui.button("Delete").clicked() {
+                        if let Some(edit) =
+                            node_record(node_ent, &graph, &ids, &nodes, &edges, &attrs, &colors)
+                        {
+                            history.push(edit);
+                        }
                         // remove all edges linked to this node
-                        delete_node(deletion.node.unwrap(), &mut graph, &mut commands, &edges);
+                        delete_node(node_ent, &mut graph, &mut commands, &edges);
                         deletion.node = None;
                     }
                 });
@@ -565,7 +804,13 @@ fn deletion_popup(
                         deletion.edge = None;
                     }
                     if ui.button("Delete").clicked() {
-                        delete_edge(deletion.edge.unwrap(), &mut graph, &mut commands, &edges);
+                        if let Ok((_, edge)) = edges.get(edge_ent)
+                            && let Some(record) = edge_record(edge, attrs.get(edge_ent).ok(), &ids)
+                        {
+                            let nth = parallel_index(&graph, &edges, edge_ent);
+                            history.push(Edit::RemoveEdge(record, nth));
+                        }
+                        delete_edge(edge_ent, &mut graph, &mut commands, &edges);
                         deletion.edge = None;
                     }
                 });
@@ -588,20 +833,14 @@ fn delete_node(
         .collect();
 
     // 2. Despawn those edges
-    for edge_ent in &edges_to_remove {
-        commands.entity(*edge_ent).despawn();
-        graph.edges.retain(|&e| e != *edge_ent);
+    for edge_ent in edges_to_remove {
+        delete_edge(edge_ent, graph, commands, edge_query);
     }
 
-    // 3. Remove node from adjacency list
-    graph.adj.remove(&node);
-
-    // 4. Remove node from other adjacency lists
-    for neighbors in graph.adj.values_mut() {
-        neighbors.retain(|&n| n != node);
-    }
+    // 3. Remove node from adjacency lists
+    graph.remove_node(node);
 
-    // 5. Despawn node itself
+    // 4. Despawn node itself
     commands.entity(node).despawn();
 }
 
@@ -612,26 +851,26 @@ fn delete_edge(
     edge_query: &Query<(Entity, &GEdge)>,
 ) {
     if let Ok((_, edge)) = edge_query.get(edge_ent) {
-        // Remove from adjacency list
-        if let Some(neighbors) = graph.adj.get_mut(&edge.from) {
-            neighbors.retain(|&n| n != edge.to);
-        }
-
-        // Remove edge record
-        graph.edges.retain(|&e| e != edge_ent);
+        // Remove from adjacency list, weights and edge record
+        graph.disconnect(edge_ent, edge.from, edge.to);
 
         // Despawn edge
         commands.entity(edge_ent).despawn();
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn ui_system(
     mut egui_ctx: EguiContexts,
     mut config: ResMut<Config>,
     mut next_state: ResMut<NextState<AppMode>>,
-    mut writer: MessageWriter<ExecLuaScript>,
-    mut writer2: MessageWriter<StepLua>,
-    manager: Res<LuaManager>,
+    mut graph: ResMut<Graph>,
+    edges: Query<(Entity, &GEdge)>,
+    history: Res<History>,
+    mut steps: MessageWriter<HistoryStep>,
+    mut run_layout: MessageWriter<RunLayout>,
+    mut layout_state: ResMut<LayoutState>,
+    mut sim: ResMut<Simulation>,
 ) -> Result {
     egui::Window::new("Mode").show(egui_ctx.ctx_mut()?, |ui| {
         if ui.button("View").clicked() {
@@ -643,37 +882,186 @@ fn ui_system(
         if ui.button("Script").clicked() {
             next_state.set(AppMode::Script);
         }
+        let mut directed = graph.directed;
+        if ui.checkbox(&mut directed, "Directed").changed() {
+            let records: Vec<_> = graph
+                .edges
+                .iter()
+                .filter_map(|&e| edges.get(e).ok())
+                .map(|(e, edge)| (e, edge.from, edge.to, edge.weight))
+                .collect();
+            graph.set_directed(directed, records);
+        }
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!history.undo.is_empty(), egui::Button::new("Undo"))
+                .on_hover_text("Ctrl+Z")
+                .clicked()
+            {
+                steps.write(HistoryStep::Undo);
+            }
+            if ui
+                .add_enabled(!history.redo.is_empty(), egui::Button::new("Redo"))
+                .on_hover_text("Ctrl+Shift+Z")
+                .clicked()
+            {
+                steps.write(HistoryStep::Redo);
+            }
+        });
     });
     egui::Window::new("Physics settings").show(egui_ctx.ctx_mut()?, |ui| {
-        ui.checkbox(&mut config.enabled, "Enable physics");
-        ui.add(egui::Slider::new(&mut config.k_r, 0.0..=10000.0).text("Repulsion force"));
-        ui.add(egui::Slider::new(&mut config.k_g, 0.0..=4.0).text("Gravity force"));
+        let changed = [
+            ui.checkbox(&mut config.enabled, "Enable physics"),
+            ui.add(egui::Slider::new(&mut config.k_r, 0.0..=10000.0).text("Repulsion force")),
+            ui.add(egui::Slider::new(&mut config.k_g, 0.0..=4.0).text("Gravity force")),
+            ui.add(
+                egui::Slider::new(&mut config.scaling_ratio, 0.1..=10.0)
+                    .logarithmic(true)
+                    .text("Scaling ratio"),
+            )
+            .on_hover_text("Multiplies repulsion; larger spreads the graph out"),
+            ui.checkbox(&mut config.strong_gravity, "Strong gravity")
+                .on_hover_text("Gravity grows with the distance from the center"),
+            ui.checkbox(&mut config.lin_log, "LinLog attraction")
+                .on_hover_text("Logarithmic attraction, which draws clusters tighter"),
+            ui.checkbox(&mut config.dissuade_hubs, "Dissuade hubs")
+                .on_hover_text("Divides attraction by degree, pushing hubs to the borders"),
+            ui.add(
+                egui::Slider::new(&mut config.edge_influence, 0.0..=2.0)
+                    .text("Edge weight influence"),
+            )
+            .on_hover_text("0 treats all edges alike"),
+            ui.horizontal(|ui| {
+                ui.label("Edge weights set");
+                ui.selectable_value(&mut config.edge_force, EdgeForce::Attraction, "attraction")
+                    | ui.selectable_value(&mut config.edge_force, EdgeForce::Length, "length")
+            })
+            .inner,
+            ui.horizontal(|ui| {
+                ui.label("Weight attribute");
+                ui.add(
+                    egui::TextEdit::singleline(&mut config.edge_attribute)
+                        .hint_text("weight")
+                        .desired_width(100.),
+                )
+                .on_hover_text("A numeric edge attribute to use instead of the weight")
+            })
+            .inner,
+            ui.add(egui::Slider::new(&mut config.theta, 0.0..=2.0).text("Barnes-Hut theta")),
+            ui.add(
+                egui::Slider::new(&mut config.tolerance, 0.01..=10.0)
+                    .logarithmic(true)
+                    .text("Convergence threshold"),
+            ),
+            ui.checkbox(&mut config.prevent_overlap, "Prevent overlap"),
+            ui.add_enabled(
+                config.prevent_overlap,
+                egui::Slider::new(&mut config.min_spacing, 0.0..=200.0).text("Minimum spacing"),
+            ),
+            ui.checkbox(&mut config.fixed_timestep, "Fixed timestep")
+                .on_hover_text("Step at a fixed rate instead of once per frame"),
+        ]
+        .iter()
+        .any(|response| response.changed());
+        if changed {
+            sim.reheat();
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Run").clicked() {
+                sim.pending = sim.iterations;
+            }
+            ui.add(egui::DragValue::new(&mut sim.iterations).range(1..=10000));
+            ui.label("iterations");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Seed");
+            ui.add(egui::DragValue::new(&mut config.seed))
+                .on_hover_text("Used when opening a file and by the random layout");
+        });
+        let status = if sim.pending > 0 {
+            format!("running, {} steps left", sim.pending)
+        } else if sim.converged {
+            "converged".to_string()
+        } else if config.enabled {
+            "running".to_string()
+        } else {
+            "stopped".to_string()
+        };
+        ui.label(format!("Simulation {status}, energy {:.2}", sim.energy));
+        ui.checkbox(&mut config.show_weights, "Show edge weights");
+        ui.separator();
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("layout")
+                .selected_text(config.layout.name())
+                .show_ui(ui, |ui| {
+                    for kind in layout::LayoutKind::ALL {
+                        ui.selectable_value(&mut config.layout, kind, kind.name());
+                    }
+                });
+            if ui
+                .add_enabled(layout_state.task.is_none(), egui::Button::new("Apply"))
+                .clicked()
+            {
+                run_layout.write(RunLayout(config.layout));
+            }
+            if layout_state.stopped_physics {
+                if config.enabled {
+                    layout_state.stopped_physics = false;
+                } else {
+                    ui.label("Physics switched off")
+                        .on_hover_text("So that it does not undo the layout");
+                }
+            }
+        });
+        if config.layout == layout::LayoutKind::KamadaKawai
+            && graph.adj.len() > layout::STRESS_MAX_NODES
+        {
+            ui.label(format!(
+                "Over {} nodes: uses Fruchterman-Reingold instead",
+                layout::STRESS_MAX_NODES
+            ));
+        }
+        if layout_state.task.is_some() {
+            ui.label("Computing layout...");
+        }
     });
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn script_ui(
     mut egui_ctx: EguiContexts,
-    mut next_state: ResMut<NextState<AppMode>>,
     mut writer: MessageWriter<ExecLuaScript>,
     mut writer2: MessageWriter<StepLua>,
     mut manager: ResMut<LuaManager>,
-    config: ResMut<Config>,
+    config: Res<Config>,
+    mut console: ResMut<ScriptConsole>,
+    mut editor: ResMut<ScriptEditor>,
+    mut eval: MessageWriter<EvalLua>,
 ) -> Result {
     egui::Window::new("Available Scripts").show(egui_ctx.ctx_mut()?, |ui| {
         // 1. wczytujemy wszystkie pliki Lua z katalogu
         if let Ok(entries) = fs::read_dir(&config.scripts_dir) {
             for entry in entries.flatten() {
                 let path = entry.path();
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.ends_with(".lua") {
-                        if ui.button(name).clicked() {
-                            // 2. kliknięcie ładuje skrypt
-                            if let Ok(code) = fs::read_to_string(&path) {
-                                writer.write(ExecLuaScript { code });
-                            }
+                if let Some(name) = path.file_name().and_then(|n| n.to_str())
+                    && name.ends_with(".lua")
+                    && ui.button(name).clicked()
+                {
+                    // 2. kliknięcie ładuje skrypt, także do edytora, chyba że
+                    // są w nim niezapisane zmiany: wtedy edytor najpierw pyta
+                    if let Ok(code) = fs::read_to_string(&path) {
+                        if editor.is_modified() {
+                            editor.replacing = Some((name.to_string(), code.clone()));
+                        } else {
+                            editor.open(name.to_string(), code.clone());
                         }
+                        writer.write(ExecLuaScript {
+                            name: name.to_string(),
+                            code,
+                            running: false,
+                        });
                     }
                 }
             }
@@ -696,6 +1084,42 @@ fn script_ui(
         };
     });
 
+    // 4. wypisane przez skrypty i błędy, które je zatrzymały
+    egui::Window::new("Script Console").show(egui_ctx.ctx_mut()?, |ui| {
+        if ui.button("Clear").clicked() {
+            console.lines.clear();
+        }
+        egui::ScrollArea::vertical()
+            .max_height(300.)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for line in &console.lines {
+                    match line {
+                        ConsoleLine::Output(text) => ui.monospace(text),
+                        ConsoleLine::Error(text) => ui.label(
+                            egui::RichText::new(text)
+                                .monospace()
+                                .color(egui::Color32::LIGHT_RED),
+                        ),
+                    };
+                }
+            });
+        // 5. REPL: linia wykonywana od razu na bieżącym grafie
+        let input = ui.add(
+            egui::TextEdit::singleline(&mut editor.input)
+                .code_editor()
+                .hint_text("graph:len()")
+                .desired_width(f32::INFINITY),
+        );
+        if input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            let code = std::mem::take(&mut editor.input);
+            if !code.trim().is_empty() {
+                eval.write(EvalLua { code });
+            }
+            input.request_focus();
+        }
+    });
+
     Ok(())
 }
 
@@ -709,7 +1133,7 @@ fn pan_camera_system(
         pan.enabled = false;
         return Ok(());
     }
-    if drag.dragging == None {
+    if drag.dragging.is_none() {
         pan.enabled = true;
         return Ok(());
     }
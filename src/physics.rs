@@ -1,96 +1,305 @@
 use std::collections::HashMap;
 
 use bevy::prelude::*;
+use bevy::tasks::{ComputeTaskPool, ParallelSlice, TaskPool};
 
 use crate::components::*;
-use crate::compute_degrees;
+use crate::layout::EDGE_LENGTH;
 
-pub(crate) fn apply_forces(
-    mut query: Query<(Entity, &mut Transform, &GNode)>,
-    edge_query: Query<&GEdge>,
-    mut velocities: Local<HashMap<Entity, Vec2>>,
-    mut prev_forces: Local<HashMap<Entity, Vec2>>,
-    mut prev_global_speed: Local<f32>,
-    mut timestep: Local<Option<f32>>,
-    config: Res<Config>,
-) {
-    if !config.enabled {
-        return;
-    }
-    if *timestep == None {
-        *timestep = Some(20.);
+#[cfg(test)]
+mod bench;
+pub(crate) mod quadtree;
+
+use quadtree::QuadTree;
+
+/// How much harder than the usual repulsion overlapping nodes push apart.
+const OVERLAP_REPULSION: f32 = 100.;
+
+/// Simulated time per step in fixed timestep mode.
+const FIXED_STEP_SECS: f32 = 1. / 60.;
+/// Most steps taken in one frame in fixed timestep mode, so a long stall
+/// does not freeze the app while it catches up.
+const MAX_STEPS_PER_FRAME: usize = 8;
+/// Most of the steps asked for with "Run" taken in one frame; the rest
+/// carry over to the following frames.
+const MAX_RUN_STEPS_PER_FRAME: usize = 50;
+
+/// Graphs smaller than this are stepped on one thread; spreading a few
+/// hundred nodes over the task pool costs more than it saves.
+const PARALLEL_MIN_NODES: usize = 512;
+
+/// The graph in the dense form the simulation works on: node `i` is the
+/// `i`-th entry of every list, and edges refer to those indices.
+pub struct Bodies {
+    pub positions: Vec<Vec2>,
+    pub radii: Vec<f32>,
+    pub masses: Vec<f32>, // degree + 1
+    pub pinned: Vec<bool>,
+    pub edges: Vec<(usize, usize, f32)>, // (from, to, weight)
+}
+
+impl Bodies {
+    /// Bodies for `positions` joined by `edges`, with the masses worked out
+    /// from the degrees.
+    pub fn new(
+        positions: Vec<Vec2>,
+        radii: Vec<f32>,
+        pinned: Vec<bool>,
+        edges: Vec<(usize, usize, f32)>,
+    ) -> Self {
+        let mut masses = vec![1.; positions.len()];
+        for &(from, to, _) in &edges {
+            masses[from] += 1.;
+            masses[to] += 1.;
+        }
+        Bodies {
+            positions,
+            radii,
+            masses,
+            pinned,
+            edges,
+        }
     }
-    if let Some(timestep_u) = *timestep {
-        if timestep_u > 1. {
-            *timestep = Some(0.80 * timestep_u);
+}
+
+/// Velocities and forces carried from one simulation step to the next, per
+/// node in the order of `entities`.
+#[derive(Default)]
+pub struct ForceState {
+    entities: Vec<Entity>,
+    velocities: Vec<Vec2>,
+    prev_forces: Vec<Vec2>,
+    prev_global_speed: f32,
+}
+
+impl ForceState {
+    /// Lines the per-node state up with `entities`, keeping what is known
+    /// about nodes that were already there.
+    fn align(&mut self, entities: &[Entity]) {
+        if self.entities == entities {
+            return;
         }
+        let old: HashMap<Entity, usize> = self
+            .entities
+            .iter()
+            .enumerate()
+            .map(|(i, &e)| (e, i))
+            .collect();
+        let carried = |values: &[Vec2]| {
+            entities
+                .iter()
+                .map(|e| old.get(e).map_or(Vec2::ZERO, |&i| values[i]))
+                .collect()
+        };
+        self.velocities = carried(&self.velocities);
+        self.prev_forces = carried(&self.prev_forces);
+        self.entities = entities.to_vec();
     }
-    let k_r = config.k_r;
-    let k_g = config.k_g;
-    let k_s = 0.1;
-    let mut global_swinging = 0.0;
-    let mut global_traction = 0.0;
 
-    // --- 1. Gather node positions
-    let mut positions = HashMap::new();
-    for (ent, tf, _) in query.iter() {
-        positions.insert(ent, tf.translation.truncate());
+    fn resize(&mut self, len: usize) {
+        self.velocities.resize(len, Vec2::ZERO);
+        self.prev_forces.resize(len, Vec2::ZERO);
     }
+}
 
-    // --- 2. Compute attraction (edges)
-    let mut a_forces = HashMap::new();
-    for GEdge {
-        to: ent1,
-        from: ent2,
-    } in edge_query.iter()
-    {
-        let mut delta = positions[&ent2] - positions[&ent1];
-        *a_forces.entry(ent1).or_insert(Vec2::ZERO) -= delta;
-        *a_forces.entry(ent2).or_insert(Vec2::ZERO) += delta;
+/// Advances the force simulation by a step each frame (or at a fixed rate,
+/// see `Config::fixed_timestep`) until it converges, plus any iterations
+/// asked for from the physics window. Edits, drags and unpinning wake it up
+/// again.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn apply_forces(
+    mut query: Query<(Entity, &mut Transform, Has<Pinned>), With<GNode>>,
+    edge_query: Query<(&GEdge, Option<&Attributes>)>,
+    mut sim: ResMut<Simulation>,
+    mut unpinned: RemovedComponents<Pinned>,
+    config: Res<Config>,
+    graph: Res<Graph>,
+    history: Res<History>,
+    drag: Res<DragState>,
+    time: Res<Time>,
+) {
+    let sim = &mut *sim;
+    let unpinned = unpinned.read().count() > 0;
+    if graph.is_changed() || history.is_changed() || drag.dragging.is_some() || unpinned {
+        sim.reheat();
     }
+    // "Run" steps regardless, a batch per frame; otherwise one step per
+    // frame, or in fixed timestep mode as many as the time since the last
+    // frame calls for
+    let requested = sim.pending.min(MAX_RUN_STEPS_PER_FRAME);
+    sim.pending -= requested;
+    let steps = if requested > 0 {
+        requested
+    } else if !config.enabled || sim.converged {
+        sim.lag = 0.;
+        return;
+    } else if config.fixed_timestep {
+        sim.lag += time.delta_secs();
+        let steps = (sim.lag / FIXED_STEP_SECS) as usize;
+        sim.lag -= steps as f32 * FIXED_STEP_SECS;
+        steps.min(MAX_STEPS_PER_FRAME)
+    } else {
+        1
+    };
 
-    // --- 3. Compute degree-weighted repulsion
-    let degrees = compute_degrees(&edge_query);
-    let mut r_forces = HashMap::new();
-    for (&ent1, &pos1) in positions.iter() {
-        for (&ent2, &pos2) in positions.iter() {
-            if ent1 == ent2 {
-                continue;
-            }
-            let delta = pos1 - pos2;
-            let dist = delta.length().max(1e-6);
-            let deg_mul =
-                (degrees.get(&ent1).unwrap_or(&0) + 1) * (degrees.get(&ent2).unwrap_or(&0) + 1);
-            *r_forces.entry(ent1).or_insert(Vec2::ZERO) +=
-                delta.normalize() * (k_r * deg_mul as f32 / dist);
+    // --- Gather the graph into dense arrays, in query order
+    let entities: Vec<Entity> = query.iter().map(|(ent, _, _)| ent).collect();
+    let index: HashMap<Entity, usize> = entities.iter().enumerate().map(|(i, &e)| (e, i)).collect();
+    let edges = edge_query
+        .iter()
+        .filter_map(|(e, attrs)| {
+            Some((
+                *index.get(&e.from)?,
+                *index.get(&e.to)?,
+                edge_weight(&config, e, attrs),
+            ))
+        })
+        .collect();
+    let mut bodies = Bodies::new(
+        query
+            .iter()
+            .map(|(_, tf, _)| tf.translation.truncate())
+            .collect(),
+        query
+            .iter()
+            .map(|(_, tf, _)| NODE_RADIUS * tf.scale.x.max(tf.scale.y))
+            .collect(),
+        query.iter().map(|(_, _, pinned)| pinned).collect(),
+        edges,
+    );
+    sim.forces.align(&entities);
+
+    let moving = bodies.pinned.iter().filter(|&&pinned| !pinned).count();
+    let parallel = entities.len() >= PARALLEL_MIN_NODES;
+    for _ in 0..steps {
+        if sim.timestep > 1. {
+            sim.timestep *= 0.80;
+        }
+        sim.energy = step(
+            &mut bodies,
+            &mut sim.forces,
+            &config,
+            sim.timestep,
+            parallel,
+        );
+        // checked every step, so when it stops does not depend on frame rate
+        sim.converged = sim.energy / moving.max(1) as f32 <= config.tolerance * config.tolerance;
+        if sim.converged && requested == 0 {
+            break;
         }
     }
 
-    // --- 4. Add gravity
-    for (&ent, &pos) in positions.iter() {
-        *r_forces.entry(ent).or_insert(Vec2::ZERO) +=
-            -pos.normalize() * k_g * (*degrees.get(&ent).unwrap_or(&0) as f32 + 1.) * pos.length();
+    for ((_, mut tf, pinned), pos) in query.iter_mut().zip(&bodies.positions) {
+        if !pinned {
+            tf.translation = pos.extend(tf.translation.z);
+        }
     }
-    // --- 5. Combine forces
-    let mut current_forces = HashMap::new();
-    for (ent, _, _) in query.iter() {
-        let curr_f =
-            -a_forces.get(&ent).unwrap_or(&Vec2::ZERO) + r_forces.get(&ent).unwrap_or(&Vec2::ZERO);
-        *current_forces.entry(ent).or_insert(Vec2::ZERO) = curr_f;
+}
+
+/// The weight the simulation uses for an edge: the numeric attribute named
+/// in the settings if it has one, its `GEdge` weight otherwise.
+fn edge_weight(config: &Config, edge: &GEdge, attrs: Option<&Attributes>) -> f32 {
+    if config.edge_attribute.is_empty() {
+        return edge.weight;
     }
+    attrs
+        .and_then(|attrs| attrs.0.get(&config.edge_attribute))
+        .and_then(AttrValue::as_f64)
+        .map_or(edge.weight, |v| v as f32)
+}
 
-    // --- 6. Calculate the global speed
-    for (ent, curr_f) in current_forces.iter() {
-        let prev_f = prev_forces.get(ent).unwrap_or(&Vec2::ZERO);
-        let deg = (degrees.get(ent).unwrap_or(&0) + 1) as f32;
+/// One step of the simulation, moving `bodies.positions`. The per-node
+/// forces are computed on all cores when `parallel` is set; every node's
+/// force is summed in the same order either way, so the result does not
+/// depend on it. Returns the sum of the squared distances the nodes moved.
+pub fn step(
+    bodies: &mut Bodies,
+    state: &mut ForceState,
+    config: &Config,
+    timestep: f32,
+    parallel: bool,
+) -> f32 {
+    let n = bodies.positions.len();
+    state.resize(n);
+    let ForceState {
+        velocities,
+        prev_forces,
+        prev_global_speed,
+        ..
+    } = state;
+    let Bodies {
+        positions,
+        radii,
+        masses,
+        edges,
+        ..
+    } = &*bodies;
+    let k_r = config.k_r * config.scaling_ratio;
+    let k_g = config.k_g;
+    let k_s = 0.1;
 
-        let swinging = (*curr_f - *prev_f).length();
-        let traction = ((*curr_f + *prev_f) * 0.5).length();
+    // --- 1. Degree-weighted repulsion and gravity, per node
+    // Each node repels every other with k_r * (deg1 + 1) * (deg2 + 1) / dist;
+    // the quadtree sums the (deg2 + 1) / dist part (Barnes–Hut). Strong
+    // gravity grows with the distance from the origin; otherwise it pulls
+    // as hard everywhere as strong gravity does one edge length out.
+    let tree = QuadTree::new(positions.iter().copied().zip(masses.iter().copied()));
+    let grid = config
+        .prevent_overlap
+        .then(|| OverlapGrid::new(positions, radii, config.min_spacing));
+    let mut forces = par_map(n, parallel, |i| {
+        let pos = positions[i];
+        let mut f = tree.repulsion(pos, config.theta) * (k_r * masses[i]);
+        f -= if config.strong_gravity {
+            pos
+        } else {
+            pos.normalize_or_zero() * EDGE_LENGTH
+        } * (k_g * masses[i]);
+        // nodes whose circles come closer than the minimum spacing push
+        // each other away much harder (ForceAtlas2's "prevent overlap")
+        if let Some(grid) = &grid {
+            f += grid.push(i, positions, radii, masses) * (k_r * OVERLAP_REPULSION);
+        }
+        f
+    });
 
-        global_swinging += deg * swinging;
-        global_traction += deg * traction;
+    // --- 2. Attraction along edges: heavier edges pull harder, or are
+    // springs of a longer rest length; negative weights count as 0. LinLog
+    // pulls with the logarithm of the stretch instead, which draws clusters
+    // tighter, and dissuading hubs divides the pull by the source's degree
+    // so that hubs end up at the borders (both from ForceAtlas2).
+    for &(from, to, weight) in edges {
+        let w = weight.max(0.).powf(config.edge_influence);
+        let delta = positions[from] - positions[to];
+        let len = delta.length();
+        if len <= 1e-6 {
+            continue;
+        }
+        let (stretch, strength) = match config.edge_force {
+            EdgeForce::Attraction => (len, w),
+            EdgeForce::Length => (len - EDGE_LENGTH * w, 1.),
+        };
+        let stretch = if config.lin_log {
+            stretch.signum() * stretch.abs().ln_1p()
+        } else {
+            stretch
+        };
+        let mut pull = delta / len * (stretch * strength);
+        if config.dissuade_hubs {
+            pull /= masses[from];
+        }
+        forces[to] += pull;
+        forces[from] -= pull;
     }
 
+    // --- 3. Calculate the global speed
+    let mut global_swinging = 0.0;
+    let mut global_traction = 0.0;
+    for i in 0..n {
+        let (curr_f, prev_f) = (forces[i], prev_forces[i]);
+        global_swinging += masses[i] * (curr_f - prev_f).length();
+        global_traction += masses[i] * ((curr_f + prev_f) * 0.5).length();
+    }
     let global_speed = if global_swinging > 1e-6 {
         0.1 * (global_traction / global_swinging) * 0.5 + *prev_global_speed * 0.5
     } else {
@@ -99,31 +308,117 @@ pub(crate) fn apply_forces(
     let global_speed = global_speed.clamp(0.01, 10.0);
     *prev_global_speed = global_speed;
 
-    // --- 7. Adaptive local speed
-    for (ent, _, _) in query.iter() {
-        let prev_f = prev_forces.entry(ent).or_insert(Vec2::ZERO);
-        let curr_f = current_forces[&ent];
-
-        let swinging = (*prev_f - curr_f).length();
+    // --- 4. Adaptive local speed
+    let damping = 0.95; // 0.8-0.9 is typical
+    for i in 0..n {
+        let curr_f = forces[i];
+        let swinging = (prev_forces[i] - curr_f).length();
 
         let mut s = k_s * global_speed / (1. + global_speed * swinging.sqrt());
         if s > 10. / curr_f.length() {
             s = 10. / curr_f.length();
         }
         let res = curr_f * s;
-        *prev_forces.entry(ent).or_insert(Vec2::ZERO) = res;
-        *velocities.entry(ent).or_insert(Vec2::ZERO) += res;
+        prev_forces[i] = res;
+        velocities[i] = (velocities[i] + res) * damping;
     }
 
-    let damping = 0.95; // 0.8-0.9 is typical
-    for v in velocities.values_mut() {
-        *v *= damping;
+    // --- 5. Integrate positions; pinned nodes stay put but still took part
+    // in the forces above
+    let mut energy = 0.;
+    for ((pos, velocity), &pinned) in bodies
+        .positions
+        .iter_mut()
+        .zip(velocities.iter_mut())
+        .zip(&bodies.pinned)
+    {
+        if pinned {
+            *velocity = Vec2::ZERO;
+            continue;
+        }
+        let moved = *velocity * timestep;
+        *pos += moved;
+        energy += moved.length_squared();
+    }
+    energy
+}
+
+/// `f(0), .., f(n - 1)`, spread over the compute task pool if `parallel`.
+fn par_map<T: Send + 'static>(
+    n: usize,
+    parallel: bool,
+    f: impl Fn(usize) -> T + Send + Sync,
+) -> Vec<T> {
+    if !parallel {
+        return (0..n).map(f).collect();
     }
+    let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let chunk = n.div_ceil(pool.thread_num().max(1)).max(1);
+    let indices: Vec<usize> = (0..n).collect();
+    indices
+        .par_chunk_map(pool, chunk, |_, chunk| {
+            chunk.iter().map(|&i| f(i)).collect::<Vec<_>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+}
 
-    // --- 8. Integrate positions
-    for (ent, mut tf, _) in query.iter_mut() {
-        if let Some(v) = velocities.get(&ent) {
-            tf.translation += v.extend(0.0) * timestep.unwrap();
+/// Nodes bucketed into a grid with cells as wide as the largest distance at
+/// which two of them can overlap, so the nodes a node may overlap are all
+/// in the 3 × 3 cells around its own.
+struct OverlapGrid {
+    reach: f32,
+    spacing: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl OverlapGrid {
+    fn new(positions: &[Vec2], radii: &[f32], spacing: f32) -> Self {
+        let reach = radii.iter().cloned().fold(0., f32::max) * 2. + spacing;
+        let mut grid = OverlapGrid {
+            reach: reach.max(f32::EPSILON),
+            spacing,
+            cells: HashMap::new(),
+        };
+        for (i, &pos) in positions.iter().enumerate() {
+            grid.cells.entry(grid.cell(pos)).or_default().push(i);
+        }
+        grid
+    }
+
+    fn cell(&self, p: Vec2) -> (i32, i32) {
+        (
+            (p.x / self.reach).floor() as i32,
+            (p.y / self.reach).floor() as i32,
+        )
+    }
+
+    /// Sum over the nodes whose circle comes within the spacing of node
+    /// `i`'s of `mass_i * mass_j`, along the direction away from them.
+    fn push(&self, i: usize, positions: &[Vec2], radii: &[f32], masses: &[f32]) -> Vec2 {
+        let (cx, cy) = self.cell(positions[i]);
+        let mut push = Vec2::ZERO;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let Some(others) = self.cells.get(&(cx + dx, cy + dy)) else {
+                    continue;
+                };
+                for &j in others.iter().filter(|&&j| j != i) {
+                    let delta = positions[i] - positions[j];
+                    if delta.length() >= radii[i] + radii[j] + self.spacing {
+                        continue;
+                    }
+                    // nodes on the same spot still need a way out, opposite
+                    // ways for the two of them
+                    let away = delta.try_normalize().unwrap_or_else(|| {
+                        let dir = Vec2::from_angle(i.min(j) as f32);
+                        if i < j { dir } else { -dir }
+                    });
+                    push += away * (masses[i] * masses[j]);
+                }
+            }
         }
+        push
     }
 }
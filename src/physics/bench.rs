@@ -0,0 +1,92 @@
+//! Checks that the parallel step matches the serial one, and times both:
+//! `cargo test --release bench_physics -- --ignored --nocapture`.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use super::{Bodies, ForceState, PARALLEL_MIN_NODES, step};
+use crate::components::{Config, NODE_RADIUS};
+use crate::layout::EDGE_LENGTH;
+
+/// Graph sizes timed by `bench_physics`.
+const SIZES: [usize; 2] = [1_000, 10_000];
+/// Simulation steps timed per graph and mode.
+const STEPS: usize = 20;
+
+fn config() -> Config {
+    Config {
+        prevent_overlap: true,
+        ..default()
+    }
+}
+
+#[test]
+fn parallel_step_matches_serial() {
+    let config = config();
+    let n = 2 * PARALLEL_MIN_NODES;
+    let (_, serial) = time_steps(&config, n, 5, false);
+    let (_, parallel) = time_steps(&config, n, 5, true);
+    assert!(serial.iter().all(|p| p.is_finite()));
+    assert_eq!(serial, parallel);
+}
+
+/// Times the physics step on generated graphs, on one thread and on the
+/// compute task pool, and prints the speedup.
+#[test]
+#[ignore = "benchmark; build with --release and pass --nocapture"]
+fn bench_physics() {
+    let config = config();
+    println!("{STEPS} physics steps per run, theta {}", config.theta);
+    println!(
+        "{:>8} {:>14} {:>14} {:>8}",
+        "nodes", "1 thread", "parallel", "speedup"
+    );
+    for n in SIZES {
+        let (serial, _) = time_steps(&config, n, STEPS, false);
+        let (parallel, _) = time_steps(&config, n, STEPS, true);
+        println!(
+            "{n:>8} {:>11.1} ms {:>11.1} ms {:>7.2}x",
+            per_step(serial),
+            per_step(parallel),
+            serial.as_secs_f64() / parallel.as_secs_f64(),
+        );
+    }
+}
+
+fn per_step(total: Duration) -> f64 {
+    total.as_secs_f64() * 1000. / STEPS as f64
+}
+
+/// Runs `steps` steps over a fresh copy of the `n`-node graph; returns how
+/// long they took and where the nodes ended up.
+fn time_steps(config: &Config, n: usize, steps: usize, parallel: bool) -> (Duration, Vec<Vec2>) {
+    let mut bodies = generate(n);
+    let mut state = ForceState::default();
+    let start = Instant::now();
+    for _ in 0..steps {
+        step(&mut bodies, &mut state, config, 1., parallel);
+    }
+    (start.elapsed(), bodies.positions)
+}
+
+/// A random tree with as many extra random edges again, scattered over a
+/// square, so it looks like a freshly opened file.
+fn generate(n: usize) -> Bodies {
+    let mut rng = StdRng::seed_from_u64(n as u64);
+    let half = EDGE_LENGTH * (n as f32).sqrt() / 2.;
+    let positions = (0..n)
+        .map(|_| {
+            Vec2::new(
+                rng.random_range(-half..=half),
+                rng.random_range(-half..=half),
+            )
+        })
+        .collect();
+    let mut edges: Vec<_> = (1..n).map(|i| (rng.random_range(0..i), i, 1.)).collect();
+    for _ in 0..n {
+        edges.push((rng.random_range(0..n), rng.random_range(0..n), 1.));
+    }
+    Bodies::new(positions, vec![NODE_RADIUS; n], vec![false; n], edges)
+}
@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+
+/// Cells are not split below this depth, so bodies sharing a position end up
+/// together in one leaf instead of recursing forever.
+const MAX_DEPTH: u32 = 24;
+
+/// A square region of the plane with the total mass and center of mass of
+/// the bodies inside it.
+struct Cell {
+    min: Vec2,
+    size: f32,
+    mass: f32,
+    center: Vec2,              // center of mass
+    children: Option<u32>,     // index of the first of four consecutive children
+    body: Option<(Vec2, f32)>, // the single body of a leaf, until it is split
+}
+
+impl Cell {
+    fn new(min: Vec2, size: f32) -> Self {
+        Cell {
+            min,
+            size,
+            mass: 0.,
+            center: Vec2::ZERO,
+            children: None,
+            body: None,
+        }
+    }
+
+    fn contains(&self, pos: Vec2) -> bool {
+        let rel = pos - self.min;
+        rel.x >= 0. && rel.y >= 0. && rel.x <= self.size && rel.y <= self.size
+    }
+
+    fn quadrant(&self, pos: Vec2) -> u32 {
+        let mid = self.min + Vec2::splat(self.size / 2.);
+        (pos.x >= mid.x) as u32 + 2 * (pos.y >= mid.y) as u32
+    }
+}
+
+/// Barnes–Hut quadtree over weighted points, used to approximate the
+/// repulsion between all pairs of nodes in O(n log n).
+pub struct QuadTree {
+    cells: Vec<Cell>,
+}
+
+impl QuadTree {
+    pub fn new(bodies: impl IntoIterator<Item = (Vec2, f32)> + Clone) -> Self {
+        let (lo, hi) = bodies.clone().into_iter().fold(
+            (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+            |(lo, hi), (p, _)| (lo.min(p), hi.max(p)),
+        );
+        let size = (hi - lo).max_element().max(1.);
+        let mut tree = QuadTree {
+            cells: vec![Cell::new(lo, size)],
+        };
+        for (pos, mass) in bodies {
+            tree.insert(pos, mass);
+        }
+        tree
+    }
+
+    fn insert(&mut self, pos: Vec2, mass: f32) {
+        let mut i = 0;
+        let mut depth = 0;
+        loop {
+            let cell = &mut self.cells[i];
+            let was_empty = cell.mass == 0.;
+            let total = cell.mass + mass;
+            cell.center = (cell.center * cell.mass + pos * mass) / total;
+            cell.mass = total;
+
+            if let Some(first) = cell.children {
+                i = (first + cell.quadrant(pos)) as usize;
+                depth += 1;
+                continue;
+            }
+            if was_empty {
+                cell.center = pos; // exactly, so the body can recognise itself
+                cell.body = Some((pos, mass));
+                return;
+            }
+            if depth >= MAX_DEPTH {
+                // too deep to split; the leaf keeps only the aggregate
+                cell.body = None;
+                return;
+            }
+
+            // split the leaf and push its body down a level
+            let (min, half) = (cell.min, cell.size / 2.);
+            let first = self.cells.len() as u32;
+            let old = self.cells[i].body.take();
+            self.cells[i].children = Some(first);
+            for q in 0..4 {
+                let offset = Vec2::new((q % 2) as f32, (q / 2) as f32) * half;
+                self.cells.push(Cell::new(min + offset, half));
+            }
+            if let Some((old_pos, old_mass)) = old {
+                let j = (first + self.cells[i].quadrant(old_pos)) as usize;
+                let child = &mut self.cells[j];
+                child.mass = old_mass;
+                child.center = old_pos;
+                child.body = Some((old_pos, old_mass));
+            }
+            i = (first + self.cells[i].quadrant(pos)) as usize;
+            depth += 1;
+        }
+    }
+
+    /// Sum of `mass * delta / dist²` over all bodies, with `delta` pointing
+    /// from each body to `pos`. Cells that look smaller than `theta` from
+    /// `pos` are treated as a single body at their center of mass; a theta
+    /// of 0 gives the exact sum. Cells containing `pos` are always opened,
+    /// and bodies at `pos` itself (or too close to it to be told apart) are
+    /// skipped.
+    pub fn repulsion(&self, pos: Vec2, theta: f32) -> Vec2 {
+        let mut force = Vec2::ZERO;
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let cell = &self.cells[i];
+            if cell.mass == 0. {
+                continue;
+            }
+            let delta = pos - cell.center;
+            let dist2 = delta.length_squared();
+            match cell.children {
+                Some(first)
+                    if cell.contains(pos) || cell.size * cell.size >= theta * theta * dist2 =>
+                {
+                    stack.extend((first..first + 4).map(|c| c as usize));
+                }
+                // a leaf holding `pos` itself, or bodies stacked on top of it;
+                // rounding in the running center of mass can leave the stack
+                // a hair off `pos`, or `pos` a hair outside the tiny cell
+                None if cell
+                    .body
+                    .map_or(dist2 <= 2. * cell.size * cell.size, |(p, _)| p == pos) => {}
+                _ => force += delta * (cell.mass / dist2),
+            }
+        }
+        force
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn theta_zero_is_the_exact_sum() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let bodies: Vec<(Vec2, f32)> = (0..200)
+            .map(|_| {
+                let pos = Vec2::new(
+                    rng.random_range(-500.0..500.),
+                    rng.random_range(-500.0..500.),
+                );
+                (pos, rng.random_range(1.0..5.))
+            })
+            .collect();
+        let tree = QuadTree::new(bodies.iter().copied());
+
+        for (i, &(pos, _)) in bodies.iter().enumerate() {
+            let direct: Vec2 = bodies
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, &(p, m))| (pos - p) * (m / (pos - p).length_squared()))
+                .sum();
+            let approx = tree.repulsion(pos, 0.);
+            assert!(
+                (approx - direct).length() <= 1e-4 * direct.length().max(1.),
+                "body {i}: {approx} != {direct}"
+            );
+        }
+    }
+
+    #[test]
+    fn coincident_bodies_stay_finite() {
+        let bodies = vec![(Vec2::new(3., 4.), 1.); 10]
+            .into_iter()
+            .chain([(Vec2::new(-20., 8.), 2.)])
+            .collect::<Vec<_>>();
+        let tree = QuadTree::new(bodies.iter().copied());
+        for theta in [0., 0.9] {
+            for &(pos, _) in &bodies {
+                assert!(tree.repulsion(pos, theta).is_finite());
+            }
+        }
+        // the stacked bodies do not push each other, only the lone one does
+        let force = tree.repulsion(Vec2::new(3., 4.), 0.);
+        let delta = Vec2::new(23., -4.);
+        assert!((force - delta * (2. / delta.length_squared())).length() < 1e-5);
+    }
+}
@@ -1,83 +1,341 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::sync::*;
 
 use bevy::prelude::*;
 use mlua::prelude::*;
 
-use mlua::{Thread, Value};
-
 use crate::components::*;
+use crate::formats::{EdgeData, NodeData};
+use crate::history::{edge_record, node_record, parallel_edges, set_label};
+use crate::layout::EDGE_LENGTH;
+use crate::loader::{spawn_edge, spawn_node};
+use crate::{delete_edge, delete_node};
+
+/// Klucz w rejestrze Lua funkcji przydzielającej id nowym węzłom, podmienianej
+/// przy każdym wznowieniu skryptu (żyje tylko w czasie `run_lua_scripts`).
+const NEXT_NODE_ID: &str = "next_node_id";
+/// Kąt między kolejnymi węzłami dodanymi bez pozycji.
+const GOLDEN_ANGLE: f32 = 2.399_963;
 
 pub fn spawn_lua_scripts(
     mut manager: ResMut<LuaManager>,
     mut new_script: MessageReader<ExecLuaScript>,
-    graph: Res<Graph>,
+    mut console: ResMut<ScriptConsole>,
 ) {
     for mes in new_script.read() {
-        let lua = Lua::new();
-        let ev = Arc::new(Mutex::new(Vec::<ScriptCommand>::new()));
-
-        let set_color_buf = Arc::clone(&ev);
-        let set_color = lua
-            .create_function(move |_, (node, color): (u64, String)| {
-                set_color_buf
-                    .lock()
-                    .unwrap()
-                    .push(ScriptCommand::SetColor(node, color));
-                Ok(())
-            })
-            .unwrap();
-
-        let reset_color_buf = Arc::clone(&ev);
-        let reset_color = lua
-            .create_function(move |_, node: u64| {
-                reset_color_buf
-                    .lock()
-                    .unwrap()
-                    .push(ScriptCommand::ResetColor(node));
-                Ok(())
-            })
-            .unwrap();
-
-        let globals = lua.globals();
-        globals.set("set_color", set_color).unwrap();
-        globals.set("reset_color", reset_color).unwrap();
-        let lua_graph = LuaGraph {
-            inner: Arc::new(Mutex::new(graph.clone())),
+        // błąd składni zgłaszamy w konsoli, a skryptu nie uruchamiamy
+        manager.active_script = match new_state(&mes.name, &mes.code) {
+            Ok(mut state) => {
+                state.running = mes.running;
+                Some(state)
+            }
+            Err(e) => {
+                console.push(ConsoleLine::Error(e.to_string()));
+                None
+            }
         };
-        globals.set("graph", lua_graph).unwrap();
-
-        let func = lua.load(&mes.code).into_function().unwrap();
-        let thread = lua.create_thread(func).unwrap();
-
-        manager.active_script = Some(LuaThreadState {
-            lua,
-            thread,
-            event_buffer: ev,
-            running: false,
-            speed: 1.,
-        });
     }
 }
 
-pub fn run_lua_scripts(mut manager: ResMut<LuaManager>, mut step_lua: MessageReader<StepLua>) {
+/// Nowy stan Lua z funkcjami grephite, gotowy do wykonania `code`.
+///
+/// Funkcje sprawdzają argumenty od razu, więc zły argument przerywa skrypt
+/// błędem z numerem linii, zamiast trafić do kolejki poleceń.
+fn new_state(name: &str, code: &str) -> LuaResult<LuaThreadState> {
+    let lua = Lua::new();
+    let ev = Arc::new(Mutex::new(Vec::<ScriptCommand>::new()));
+    // wypełniany przed pierwszym krokiem
+    let shared_graph = Arc::new(Mutex::new(ScriptGraph::default()));
+
+    // print trafia do konsoli skryptów zamiast na stdout
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let print_buf = Arc::clone(&output);
+    let print = lua.create_function(move |_, args: LuaMultiValue| {
+        let parts = args
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<LuaResult<Vec<_>>>()?;
+        print_buf.lock().unwrap().push(parts.join("\t"));
+        Ok(())
+    })?;
+
+    let set_color_buf = Arc::clone(&ev);
+    let set_color_graph = Arc::clone(&shared_graph);
+    let set_color = lua.create_function(move |_, (NodeId(node), color): (NodeId, String)| {
+        set_color_graph.lock().unwrap().check_node(node)?;
+        // błędny kolor to błąd w skrypcie, z numerem linii
+        let color = color_from_hex(&color).ok_or_else(|| {
+            LuaError::runtime(format!(
+                "invalid color {color:?}, expected #rgb, #rrggbb or #rrggbbaa"
+            ))
+        })?;
+        set_color_buf
+            .lock()
+            .unwrap()
+            .push(ScriptCommand::SetColor(node, color));
+        Ok(())
+    })?;
+
+    let reset_color_buf = Arc::clone(&ev);
+    let reset_color_graph = Arc::clone(&shared_graph);
+    let reset_color = lua.create_function(move |_, NodeId(node)| {
+        reset_color_graph.lock().unwrap().check_node(node)?;
+        reset_color_buf
+            .lock()
+            .unwrap()
+            .push(ScriptCommand::ResetColor(node));
+        Ok(())
+    })?;
+
+    let set_pinned_buf = Arc::clone(&ev);
+    let set_pinned_graph = Arc::clone(&shared_graph);
+    let set_pinned = lua.create_function(move |_, (NodeId(node), pinned): (NodeId, bool)| {
+        set_pinned_graph.lock().unwrap().check_node(node)?;
+        set_pinned_buf
+            .lock()
+            .unwrap()
+            .push(ScriptCommand::SetPinned(node, pinned));
+        Ok(())
+    })?;
+
+    // id nowego węzła jest znane od razu, choć sam węzeł powstaje dopiero
+    // w exec_lua_events
+    let add_node_buf = Arc::clone(&ev);
+    let add_node_graph = Arc::clone(&shared_graph);
+    let add_node = lua.create_function(
+        move |lua, (label, x, y): (Option<String>, Option<f32>, Option<f32>)| {
+            let pos = match (x, y) {
+                (Some(x), Some(y)) => Some(Vec2::new(finite("x", x)?, finite("y", y)?)),
+                (None, None) => None,
+                _ => return Err(LuaError::runtime("add_node needs both x and y, or neither")),
+            };
+            let next_id: LuaFunction = lua.named_registry_value(NEXT_NODE_ID)?;
+            let id: usize = next_id.call(())?;
+            add_node_graph.lock().unwrap().queued.insert(id, true);
+            add_node_buf
+                .lock()
+                .unwrap()
+                .push(ScriptCommand::AddNode { id, label, pos });
+            Ok(id)
+        },
+    )?;
+
+    let add_edge_buf = Arc::clone(&ev);
+    let add_edge_graph = Arc::clone(&shared_graph);
+    let add_edge = lua.create_function(
+        move |_, (NodeId(from), NodeId(to), weight): (NodeId, NodeId, Option<f32>)| {
+            let graph = add_edge_graph.lock().unwrap();
+            graph.check_node(from)?;
+            graph.check_node(to)?;
+            let weight = finite("weight", weight.unwrap_or(1.))?;
+            add_edge_buf
+                .lock()
+                .unwrap()
+                .push(ScriptCommand::AddEdge(from, to, weight));
+            Ok(())
+        },
+    )?;
+
+    let remove_node_buf = Arc::clone(&ev);
+    let remove_node_graph = Arc::clone(&shared_graph);
+    let remove_node = lua.create_function(move |_, NodeId(node)| {
+        let mut graph = remove_node_graph.lock().unwrap();
+        graph.check_node(node)?;
+        graph.queued.insert(node, false);
+        remove_node_buf
+            .lock()
+            .unwrap()
+            .push(ScriptCommand::RemoveNode(node));
+        Ok(())
+    })?;
+
+    let remove_edge_buf = Arc::clone(&ev);
+    let remove_edge_graph = Arc::clone(&shared_graph);
+    let remove_edge = lua.create_function(move |_, (NodeId(from), NodeId(to))| {
+        let graph = remove_edge_graph.lock().unwrap();
+        graph.check_node(from)?;
+        graph.check_node(to)?;
+        remove_edge_buf
+            .lock()
+            .unwrap()
+            .push(ScriptCommand::RemoveEdge(from, to));
+        Ok(())
+    })?;
+
+    let set_label_buf = Arc::clone(&ev);
+    let set_label_graph = Arc::clone(&shared_graph);
+    let set_label = lua.create_function(move |_, (NodeId(node), label): (NodeId, String)| {
+        set_label_graph.lock().unwrap().check_node(node)?;
+        set_label_buf
+            .lock()
+            .unwrap()
+            .push(ScriptCommand::SetLabel(node, label));
+        Ok(())
+    })?;
+
+    let globals = lua.globals();
+    globals.set("print", print)?;
+    globals.set("set_color", set_color)?;
+    globals.set("reset_color", reset_color)?;
+    globals.set("set_pinned", set_pinned)?;
+    globals.set("add_node", add_node)?;
+    globals.set("add_edge", add_edge)?;
+    globals.set("remove_node", remove_node)?;
+    globals.set("remove_edge", remove_edge)?;
+    globals.set("set_label", set_label)?;
+    globals.set(
+        "graph",
+        LuaGraph {
+            inner: Arc::clone(&shared_graph),
+        },
+    )?;
+
+    let func = lua
+        .load(code)
+        .set_name(format!("@{name}"))
+        .into_function()?;
+    let thread = lua.create_thread(func)?;
+    Ok(LuaThreadState {
+        lua,
+        thread,
+        event_buffer: ev,
+        output,
+        graph: shared_graph,
+        step_held: false,
+        running: false,
+        speed: 1.,
+    })
+}
+
+/// `value` argumentu `name`, o ile jest skończoną liczbą.
+fn finite(name: &str, value: f32) -> LuaResult<f32> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(LuaError::runtime(format!(
+            "{name} must be a finite number, got {value}"
+        )))
+    }
+}
+
+pub fn run_lua_scripts(
+    mut manager: ResMut<LuaManager>,
+    mut step_lua: MessageReader<StepLua>,
+    mut graph: ResMut<Graph>,
+    nodes: Query<(Entity, &GNode, &Transform)>,
+    edges: Query<(Entity, &GEdge)>,
+    mut console: ResMut<ScriptConsole>,
+) {
+    let manager = &mut *manager;
     if let Some(state) = &mut manager.active_script {
         // sprawdzamy, czy Step dotyczy bieżącego skryptu
-        let do_step = step_lua.read().next().is_some();
-        if do_step {
-            if state.thread.status() != LuaThreadStatus::Finished {
-                if let Err(e) = state.thread.resume::<Value>(Value::NULL) {
-                    eprintln!("Lua error: {e}");
-                }
+        let do_step = step_lua.read().next().is_some() || state.step_held;
+        // krok czeka, aż zmiany z poprzedniego zostaną zastosowane, żeby
+        // skrypt je zobaczył
+        state.step_held = do_step && !manager.pending.is_empty();
+        if do_step && !state.step_held {
+            if state.thread.status() == LuaThreadStatus::Resumable {
+                resume(state, &mut graph, &nodes, &edges, &mut console);
             } else {
-                // jeśli coroutine się skończył, usuwamy aktywny skrypt
+                // jeśli coroutine się skończył (albo przerwał go błąd),
+                // usuwamy aktywny skrypt
                 manager.active_script = None;
             }
         }
     }
 }
 
-pub fn flush_lua_events(mut manager: ResMut<LuaManager>, mut writer: MessageWriter<ScriptCommand>) {
-    if let Some(state) = &mut manager.active_script {
+/// Wykonuje w konsoli wpisane linie, każdą w nowym wątku tego samego stanu
+/// Lua, więc zmienne globalne przechodzą z linii na linię.
+pub fn eval_lua_repl(
+    mut lines: MessageReader<EvalLua>,
+    mut manager: ResMut<LuaManager>,
+    mut graph: ResMut<Graph>,
+    nodes: Query<(Entity, &GNode, &Transform)>,
+    edges: Query<(Entity, &GEdge)>,
+    mut console: ResMut<ScriptConsole>,
+) {
+    for mes in lines.read() {
+        console.push(ConsoleLine::Output(format!("> {}", mes.code)));
+        if manager.repl.is_none() {
+            match new_state("repl", "") {
+                Ok(state) => manager.repl = Some(state),
+                Err(e) => {
+                    console.push(ConsoleLine::Error(e.to_string()));
+                    continue;
+                }
+            }
+        }
+        let Some(state) = &mut manager.repl else {
+            continue;
+        };
+        // najpierw próbujemy linię jako wyrażenie, żeby pokazać jego wartość
+        let load = |code: &str| state.lua.load(code).set_name("=repl").into_function();
+        let thread = load(&format!("return {}", mes.code))
+            .or_else(|_| load(&mes.code))
+            .and_then(|func| state.lua.create_thread(func));
+        match thread {
+            Ok(thread) => state.thread = thread,
+            Err(e) => {
+                console.push(ConsoleLine::Error(e.to_string()));
+                continue;
+            }
+        }
+        if let Some(values) = resume(state, &mut graph, &nodes, &edges, &mut console)
+            && !values.is_empty()
+        {
+            let shown: Vec<String> = values
+                .iter()
+                .map(|v| v.to_string().unwrap_or_else(|e| e.to_string()))
+                .collect();
+            console.push(ConsoleLine::Output(shown.join("\t")));
+        }
+    }
+}
+
+/// Wznawia wątek stanu na jeden krok, z aktualnym grafem. Wypisane przez
+/// skrypt i błąd trafiają do konsoli; zwraca to, co wątek oddał.
+fn resume(
+    state: &LuaThreadState,
+    graph: &mut ResMut<Graph>,
+    nodes: &Query<(Entity, &GNode, &Transform)>,
+    edges: &Query<(Entity, &GEdge)>,
+    console: &mut ScriptConsole,
+) -> Option<LuaMultiValue> {
+    // graf widziany przez skrypt nadąża za światem, ale tylko między
+    // krokami: w trakcie kroku się nie zmienia
+    *state.graph.lock().unwrap() = ScriptGraph::new(
+        graph,
+        nodes
+            .iter()
+            .map(|(e, n, tf)| (e, n, tf.translation.truncate())),
+        edges.iter(),
+    );
+    let last_id = Cell::new(graph.curr_id);
+    let lua = &state.lua;
+    let result = lua.scope(|scope| {
+        let next_id = scope.create_function(|_, ()| {
+            last_id.set(last_id.get() + 1);
+            Ok(last_id.get())
+        })?;
+        lua.set_named_registry_value(NEXT_NODE_ID, next_id)?;
+        state.thread.resume::<LuaMultiValue>(())
+    });
+    if last_id.get() != graph.curr_id {
+        graph.curr_id = last_id.get();
+    }
+    for line in state.output.lock().unwrap().drain(..) {
+        console.push(ConsoleLine::Output(line));
+    }
+    // komunikat mlua zawiera już plik, linię i ślad stosu
+    result
+        .inspect_err(|e| console.push(ConsoleLine::Error(e.to_string().trim_end().to_string())))
+        .ok()
+}
+
+pub fn flush_lua_events(manager: Res<LuaManager>, mut writer: MessageWriter<ScriptCommand>) {
+    for state in manager.active_script.iter().chain(&manager.repl) {
         let mut buf = state.event_buffer.lock().unwrap();
         for ev in buf.drain(..) {
             writer.write(ev);
@@ -85,22 +343,192 @@ pub fn flush_lua_events(mut manager: ResMut<LuaManager>, mut writer: MessageWrit
     }
 }
 
-pub fn exec_lua_events(mut reader: MessageReader<ScriptCommand>, mut colors: ResMut<NodeColors>) {
-    for mes in reader.read() {
-        match mes {
-            ScriptCommand::SetColor(node, color) => {
-                *colors
-                    .colors
-                    .entry(Entity::from_bits(*node))
-                    .or_insert(Color::BLACK) = color_from_hex(color).unwrap();
+#[allow(clippy::too_many_arguments)]
+pub fn exec_lua_events(
+    mut reader: MessageReader<ScriptCommand>,
+    mut manager: ResMut<LuaManager>,
+    mut commands: Commands,
+    assets: Res<GraphAssets>,
+    asset_server: Res<AssetServer>,
+    mut graph: ResMut<Graph>,
+    mut colors: ResMut<NodeColors>,
+    mut history: ResMut<History>,
+    mut selected: ResMut<Selected>,
+    mut deletion: ResMut<DeletionRequest>,
+    ids: Query<&GNode>,
+    named: Query<(Entity, &GNode)>,
+    nodes: Query<(&Transform, Has<Pinned>), With<GNode>>,
+    edges: Query<(Entity, &GEdge)>,
+    attrs: Query<&Attributes>,
+) {
+    // Spawns and despawns only land once the frame's commands are applied.
+    // Deleting a node or edge has to see the edges as they are, so one that
+    // touches a change made earlier in the frame waits, with everything
+    // queued after it, for the next frame.
+    let mut fresh = HashSet::new(); // nodes spawned this frame
+    let mut rewired = HashSet::new(); // nodes whose edges changed this frame
+    let mut labels: HashMap<usize, String> =
+        named.iter().map(|(_, n)| (n.id, n.label.clone())).collect();
+    let mut ents: HashMap<usize, Entity> = named.iter().map(|(e, n)| (n.id, e)).collect();
+    let waiting = &mut manager.pending;
+    waiting.extend(reader.read().cloned());
+
+    // everything a script recolors in one frame is undone as a single edit
+    let mut changes = Vec::new();
+    while let Some(mes) = waiting.pop_front() {
+        let blocked = match &mes {
+            ScriptCommand::RemoveNode(node) => fresh.contains(node) || rewired.contains(node),
+            ScriptCommand::RemoveEdge(from, to) => [from, to]
+                .iter()
+                .any(|n| fresh.contains(*n) || rewired.contains(*n)),
+            _ => false,
+        };
+        if blocked {
+            waiting.push_front(mes);
+            break;
+        }
+        // recolors made before a structural change are undone after it
+        let recolor = matches!(
+            mes,
+            ScriptCommand::SetColor(..)
+                | ScriptCommand::ResetColor(_)
+                | ScriptCommand::SetPinned(..)
+        );
+        if !recolor && !changes.is_empty() {
+            history.push(Edit::Recolor(std::mem::take(&mut changes)));
+        }
+
+        let (node, new) = match mes {
+            ScriptCommand::SetColor(node, color) => (node, color),
+            ScriptCommand::ResetColor(node) => (node, Color::BLACK),
+            ScriptCommand::SetPinned(node, pinned) => {
+                if let Some(&ent) = ents.get(&node) {
+                    set_pinned(&mut commands, ent, pinned);
+                }
+                continue;
             }
-            ScriptCommand::ResetColor(node) => {
-                *colors
-                    .colors
-                    .entry(Entity::from_bits(*node))
-                    .or_insert(Color::BLACK) = Color::BLACK;
+            ScriptCommand::AddNode { id, label, pos } => {
+                let label = label.unwrap_or_else(|| id.to_string());
+                // without a position, new nodes spiral out from the origin
+                let pos = pos.unwrap_or_else(|| {
+                    Vec2::from_angle(id as f32 * GOLDEN_ANGLE) * EDGE_LENGTH * (id as f32).sqrt()
+                });
+                let ent = spawn_node(
+                    &mut commands,
+                    &assets,
+                    &asset_server,
+                    id,
+                    label.clone(),
+                    pos,
+                );
+                graph.add_node(ent);
+                colors.colors.insert(ent, Color::BLACK);
+                history.push(Edit::AddNode(
+                    NodeData {
+                        label: label.clone(),
+                        pos: Some(pos),
+                        color: Some(Color::BLACK),
+                        ..NodeData::new(id)
+                    },
+                    Vec::new(),
+                ));
+                ents.insert(id, ent);
+                labels.insert(id, label);
+                fresh.insert(id);
+                continue;
             }
-        }
+            ScriptCommand::AddEdge(from, to, weight) => {
+                let (Some(&a), Some(&b)) = (ents.get(&from), ents.get(&to)) else {
+                    continue;
+                };
+                let nth = graph.edge_count(a, b);
+                let ent = spawn_edge(&mut commands, &assets, &asset_server, a, b, weight);
+                graph.connect(ent, a, b, weight);
+                history.push(Edit::AddEdge(
+                    EdgeData {
+                        weight,
+                        ..EdgeData::new(from, to)
+                    },
+                    nth,
+                ));
+                rewired.extend([from, to]);
+                continue;
+            }
+            ScriptCommand::RemoveNode(node) => {
+                let Some(ent) = ents.remove(&node) else {
+                    continue;
+                };
+                if let Some(edit) = node_record(ent, &graph, &ids, &nodes, &edges, &attrs, &colors)
+                {
+                    history.push(edit);
+                }
+                let neighbours = graph.adj.get(&ent).into_iter().flatten();
+                let in_neighbours = graph.in_adj.get(&ent).into_iter().flatten();
+                rewired.extend(
+                    neighbours
+                        .chain(in_neighbours)
+                        .filter_map(|n| ids.get(*n).ok())
+                        .map(|n| n.id),
+                );
+                delete_node(ent, &mut graph, &mut commands, &edges);
+                colors.colors.remove(&ent);
+                if selected.0 == Some(ent) {
+                    selected.0 = None;
+                }
+                if deletion.node == Some(ent) {
+                    deletion.node = None;
+                }
+                continue;
+            }
+            ScriptCommand::RemoveEdge(from, to) => {
+                let (Some(&a), Some(&b)) = (ents.get(&from), ents.get(&to)) else {
+                    continue;
+                };
+                // of parallel edges, the first one goes
+                let found = parallel_edges(&graph, &edges, a, b).first().copied();
+                let Some((ent, edge)) = found.and_then(|ent| edges.get(ent).ok()) else {
+                    continue;
+                };
+                if let Some(record) = edge_record(edge, attrs.get(ent).ok(), &ids) {
+                    history.push(Edit::RemoveEdge(record, 0));
+                }
+                delete_edge(ent, &mut graph, &mut commands, &edges);
+                if deletion.edge == Some(ent) {
+                    deletion.edge = None;
+                }
+                rewired.extend([from, to]);
+                continue;
+            }
+            ScriptCommand::SetLabel(node, label) => {
+                if let Some(&ent) = ents.get(&node)
+                    && let Some(old) = labels.insert(node, label.clone())
+                {
+                    history.push(Edit::Relabel {
+                        id: node,
+                        old,
+                        new: label.clone(),
+                    });
+                    set_label(&mut commands, ent, node, label);
+                }
+                continue;
+            }
+        };
+        let Some(&ent) = ents.get(&node) else {
+            continue;
+        };
+        let old = colors.colors.insert(ent, new).unwrap_or(Color::BLACK);
+        changes.push((node, old, new));
+    }
+    if !changes.is_empty() {
+        history.push(Edit::Recolor(changes));
+    }
+}
+
+pub fn set_pinned(commands: &mut Commands, node: Entity, pinned: bool) {
+    if pinned {
+        commands.entity(node).insert(Pinned);
+    } else {
+        commands.entity(node).remove::<Pinned>();
     }
 }
 
@@ -135,7 +563,7 @@ pub fn color_from_hex(hex: &str) -> Option<Color> {
 
 pub fn auto_run(
     mut timel: Local<f32>,
-    mut manager: ResMut<LuaManager>,
+    manager: Res<LuaManager>,
     mut writer: MessageWriter<StepLua>,
     time: Res<Time>,
 ) {
@@ -152,3 +580,58 @@ pub fn auto_run(
         *timel = 0.;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `code` as one step against a graph of nodes 1 and 2; returns how
+    /// many commands it queued.
+    fn run(code: &str) -> LuaResult<usize> {
+        let state = new_state("test", code)?;
+        let mut graph = state.graph.lock().unwrap();
+        for id in [1, 2] {
+            let node = ScriptNode {
+                label: id.to_string(),
+                pos: Vec2::ZERO,
+                out: Vec::new(),
+                incoming: Vec::new(),
+                weights: HashMap::new(),
+            };
+            graph.nodes.insert(id, node);
+        }
+        drop(graph);
+        let last_id = Cell::new(2);
+        state.lua.scope(|scope| {
+            let next_id = scope.create_function(|_, ()| {
+                last_id.set(last_id.get() + 1);
+                Ok(last_id.get())
+            })?;
+            state.lua.set_named_registry_value(NEXT_NODE_ID, next_id)?;
+            state.thread.resume::<()>(())
+        })?;
+        Ok(state.event_buffer.lock().unwrap().len())
+    }
+
+    #[test]
+    fn bad_arguments_fail_the_script() {
+        for code in [
+            "add_edge(1, 9)",
+            "set_color(9, '#fff')",
+            "set_pinned(0, true)",
+            "add_node('a', 0/0, 1)",
+            "add_node('a', 1)",
+            "add_edge(1, 2, math.huge)",
+            "remove_node(2) set_label(2, 'x')",
+        ] {
+            let err = run(code).expect_err(code).to_string();
+            assert!(err.contains("test:1:"), "{code}: {err}");
+        }
+    }
+
+    #[test]
+    fn nodes_added_this_step_can_be_used() {
+        let code = "local n = add_node('n', 5, 5) add_edge(1, n, 2) set_color(n, '#f00')";
+        assert_eq!(run(code).unwrap(), 3);
+    }
+}